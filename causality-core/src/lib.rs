@@ -0,0 +1,7 @@
+//! Causality event definitions and wire format shared by the kernel and host tools.
+
+#![no_std]
+
+pub mod schema;
+pub mod types;
+pub mod wire;
@@ -0,0 +1,346 @@
+//! Declarative schema for event kinds and their fixed-size payloads.
+//!
+//! `event_schema!` declares payload layouts and the kinds that carry them, and generates
+//! `EventData`, `DataTag`, `EventKind`, their wire encoding, a compile-time check that
+//! every payload fits in `EVENT_DATA_LEN`, and the `DATA_DESCRIPTORS`/`KIND_DESCRIPTORS`
+//! tables. Host tools render payloads from the tables, so adding a kind or payload only
+//! touches the schema invocation in `types`.
+//!
+//! Payload fields are packed little-endian in declaration order.
+
+/// Bytes reserved for an event payload, both on the wire and as the bound for the
+/// in-memory `EventData`.
+pub const EVENT_DATA_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl FieldType {
+    pub const fn size(self) -> usize {
+        match self {
+            FieldType::U8 => 1,
+            FieldType::U16 => 2,
+            FieldType::U32 => 4,
+            FieldType::U64 => 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FieldDescriptor {
+    pub name: &'static str,
+    pub ty: FieldType,
+    /// Byte offset inside the encoded payload.
+    pub offset: usize,
+}
+
+impl FieldDescriptor {
+    /// Read this field out of an encoded payload, widened to u64.
+    pub fn read(&self, data: &[u8; EVENT_DATA_LEN]) -> u64 {
+        let bytes = &data[self.offset..self.offset + self.ty.size()];
+        let mut value = [0u8; 8];
+        value[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DataDescriptor {
+    pub tag: u8,
+    pub name: &'static str,
+    /// Encoded payload size in bytes.
+    pub size: usize,
+    pub fields: &'static [FieldDescriptor],
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct KindDescriptor {
+    pub code: u16,
+    pub name: &'static str,
+    /// Tag of the payload every event of this kind carries.
+    pub data_tag: u8,
+}
+
+/// Rust types usable as payload fields.
+pub trait WireField: Copy {
+    const TYPE: FieldType;
+    const SIZE: usize = Self::TYPE.size();
+
+    fn put(self, buf: &mut [u8; EVENT_DATA_LEN], offset: usize);
+    fn get(buf: &[u8; EVENT_DATA_LEN], offset: usize) -> Self;
+}
+
+macro_rules! wire_field {
+    ($ty:ty, $field_type:ident) => {
+        impl WireField for $ty {
+            const TYPE: FieldType = FieldType::$field_type;
+
+            fn put(self, buf: &mut [u8; EVENT_DATA_LEN], offset: usize) {
+                buf[offset..offset + Self::SIZE].copy_from_slice(&self.to_le_bytes());
+            }
+
+            fn get(buf: &[u8; EVENT_DATA_LEN], offset: usize) -> Self {
+                let mut bytes = [0u8; Self::SIZE];
+                bytes.copy_from_slice(&buf[offset..offset + Self::SIZE]);
+                <$ty>::from_le_bytes(bytes)
+            }
+        }
+    };
+}
+
+wire_field!(u8, U8);
+wire_field!(u16, U16);
+wire_field!(u32, U32);
+wire_field!(u64, U64);
+
+/// Assign packed offsets to `(name, type)` pairs in declaration order.
+pub const fn layout<const N: usize>(fields: [(&'static str, FieldType); N]) -> [FieldDescriptor; N] {
+    let mut descriptors = [FieldDescriptor { name: "", ty: FieldType::U8, offset: 0 }; N];
+    let mut offset = 0;
+    let mut idx = 0;
+    while idx < N {
+        descriptors[idx] = FieldDescriptor {
+            name: fields[idx].0,
+            ty: fields[idx].1,
+            offset,
+        };
+        offset += fields[idx].1.size();
+        idx += 1;
+    }
+    descriptors
+}
+
+/// Whether a payload of `size` encoded bytes fits in `EVENT_DATA_LEN`.
+pub const fn fits(size: usize) -> bool {
+    size <= EVENT_DATA_LEN
+}
+
+pub fn data_descriptor(tag: u8) -> Option<&'static DataDescriptor> {
+    crate::types::DATA_DESCRIPTORS.iter().find(|data| data.tag == tag)
+}
+
+pub fn kind_descriptor(code: u16) -> Option<&'static KindDescriptor> {
+    crate::types::KIND_DESCRIPTORS.iter().find(|kind| kind.code == code)
+}
+
+macro_rules! event_schema {
+    (
+        data {
+            $(
+                $(#[$data_meta:meta])*
+                $data:ident = $tag:literal $({ $($field:ident: $ty:ty),* $(,)? })?
+            ),* $(,)?
+        }
+        kinds {
+            $(
+                $(#[$kind_meta:meta])*
+                $kind:ident = $code:literal => $kind_data:ident
+            ),* $(,)?
+        }
+    ) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum EventData {
+            $(
+                $(#[$data_meta])*
+                $data $({ $($field: $ty),* })?
+            ),*
+        }
+
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum DataTag {
+            $($data = $tag),*
+        }
+
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum EventKind {
+            $(
+                $(#[$kind_meta])*
+                $kind
+            ),*
+        }
+
+        $(
+            const _: () = assert!(
+                $crate::schema::fits(0 $($(+ <$ty as $crate::schema::WireField>::SIZE)*)?),
+                concat!("payload `", stringify!($data), "` does not fit in EVENT_DATA_LEN"),
+            );
+        )*
+
+        impl DataTag {
+            pub const fn from_u8(tag: u8) -> Option<Self> {
+                match tag {
+                    $($tag => Some(DataTag::$data),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl EventData {
+            pub const fn tag(&self) -> DataTag {
+                match self {
+                    $(EventData::$data { .. } => DataTag::$data),*
+                }
+            }
+
+            pub fn encode(&self, buf: &mut [u8; $crate::schema::EVENT_DATA_LEN]) {
+                buf.fill(0);
+                match *self {
+                    $(
+                        EventData::$data $({ $($field),* })? => {
+                            let mut _offset = 0;
+                            $($(
+                                $crate::schema::WireField::put($field, buf, _offset);
+                                _offset += <$ty as $crate::schema::WireField>::SIZE;
+                            )*)?
+                        }
+                    )*
+                }
+            }
+
+            pub fn decode(tag: DataTag, buf: &[u8; $crate::schema::EVENT_DATA_LEN]) -> Self {
+                match tag {
+                    $(
+                        DataTag::$data => {
+                            let mut _offset = 0;
+                            EventData::$data $({ $(
+                                $field: {
+                                    let value = <$ty as $crate::schema::WireField>::get(buf, _offset);
+                                    _offset += <$ty as $crate::schema::WireField>::SIZE;
+                                    value
+                                }
+                            ),* })?
+                        }
+                    )*
+                }
+            }
+        }
+
+        impl EventKind {
+            pub const fn code(self) -> u16 {
+                match self {
+                    $(EventKind::$kind => $code),*
+                }
+            }
+
+            pub const fn from_code(code: u16) -> Option<Self> {
+                match code {
+                    $($code => Some(EventKind::$kind),)*
+                    _ => None,
+                }
+            }
+
+            /// Payload every event of this kind must carry.
+            pub const fn data_tag(self) -> DataTag {
+                match self {
+                    $(EventKind::$kind => DataTag::$kind_data),*
+                }
+            }
+        }
+
+        pub static DATA_DESCRIPTORS: &[$crate::schema::DataDescriptor] = &[
+            $(
+                $crate::schema::DataDescriptor {
+                    tag: $tag,
+                    name: stringify!($data),
+                    size: 0 $($(+ <$ty as $crate::schema::WireField>::SIZE)*)?,
+                    fields: &$crate::schema::layout([
+                        $($((stringify!($field), <$ty as $crate::schema::WireField>::TYPE)),*)?
+                    ]),
+                }
+            ),*
+        ];
+
+        pub static KIND_DESCRIPTORS: &[$crate::schema::KindDescriptor] = &[
+            $(
+                $crate::schema::KindDescriptor {
+                    code: $code,
+                    name: stringify!($kind),
+                    data_tag: DataTag::$kind_data as u8,
+                }
+            ),*
+        ];
+    };
+}
+
+pub(crate) use event_schema;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DATA_DESCRIPTORS, DataTag, EventData, EventKind, KIND_DESCRIPTORS};
+
+    /// One payload of every shape, with field values distinct enough to catch swaps.
+    const SAMPLES: [EventData; 9] = [
+        EventData::None,
+        EventData::Loss { core: 0x0102, first_sequence: 0x1111_2222_3333_4444, last_sequence: u64::MAX },
+        EventData::Exception { vector: 14, error_code: 0x0b, rip: 0xffff_ffff_8000_0010, cr2: 0xdead_b000 },
+        EventData::Irq { vector: 0x21, rip: 0xffff_ffff_8000_0020 },
+        EventData::Frame { phys: 0x20_0000 },
+        EventData::Mapping { virt: 0xffff_8000_0000_1000, phys: 0x1000, flags: 0x8000_0000_0000_0003 },
+        EventData::Object { cache: 7, addr: 0xffff_8000_0040_0040 },
+        EventData::FrameFault { phys: 0x3000, reason: 2 },
+        EventData::Reclaim { region: 1, frames: 512 },
+    ];
+
+    #[test]
+    fn every_payload_round_trips() {
+        for data in SAMPLES {
+            let mut buf = [0xaau8; EVENT_DATA_LEN];
+            data.encode(&mut buf);
+            assert_eq!(EventData::decode(data.tag(), &buf), data);
+            assert_eq!(DataTag::from_u8(data.tag() as u8), Some(data.tag()));
+        }
+        assert_eq!(SAMPLES.len(), DATA_DESCRIPTORS.len());
+    }
+
+    #[test]
+    fn descriptors_are_packed_and_match_the_encoding() {
+        for data in SAMPLES {
+            let descriptor = data_descriptor(data.tag() as u8).unwrap();
+            let mut buf = [0u8; EVENT_DATA_LEN];
+            data.encode(&mut buf);
+
+            let mut offset = 0;
+            for field in descriptor.fields {
+                assert_eq!(field.offset, offset, "{}.{}", descriptor.name, field.name);
+                offset += field.ty.size();
+            }
+            assert_eq!(descriptor.size, offset);
+            assert!(buf[descriptor.size..].iter().all(|&byte| byte == 0));
+
+            // Re-encode the fields from their descriptors and compare byte for byte.
+            let mut rebuilt = [0u8; EVENT_DATA_LEN];
+            for field in descriptor.fields {
+                let value = field.read(&buf).to_le_bytes();
+                rebuilt[field.offset..field.offset + field.ty.size()].copy_from_slice(&value[..field.ty.size()]);
+            }
+            assert_eq!(rebuilt, buf);
+        }
+
+        let loss = data_descriptor(DataTag::Loss as u8).unwrap();
+        let mut buf = [0u8; EVENT_DATA_LEN];
+        SAMPLES[1].encode(&mut buf);
+        assert_eq!(loss.fields[0].read(&buf), 0x0102);
+        assert_eq!(loss.fields[1].read(&buf), 0x1111_2222_3333_4444);
+        assert_eq!(loss.fields[2].offset, 10);
+    }
+
+    #[test]
+    fn kind_codes_are_unique_and_name_known_payloads() {
+        for (idx, kind) in KIND_DESCRIPTORS.iter().enumerate() {
+            let decoded = EventKind::from_code(kind.code).unwrap();
+            assert_eq!(decoded.code(), kind.code);
+            assert_eq!(decoded.data_tag() as u8, kind.data_tag);
+            assert!(data_descriptor(kind.data_tag).is_some(), "{}", kind.name);
+            assert!(KIND_DESCRIPTORS[idx + 1..].iter().all(|other| other.code != kind.code));
+        }
+        assert_eq!(kind_descriptor(EventKind::FrameFault.code()).unwrap().name, "FrameFault");
+        assert!(EventKind::from_code(u16::MAX).is_none());
+    }
+}
@@ -0,0 +1,172 @@
+use core::mem::size_of;
+
+use crate::schema::event_schema;
+
+/// Upper bound on the in-memory size of an `Event`, which fixes the ring buffer footprint.
+pub const MAX_EVENT_SIZE: usize = 128;
+
+/// Causal relationship for an event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cause {
+    /// No parent event, starts a new causal chain
+    Root(RootCause),
+    /// Direct immediate causal parent event
+    CausedBy(EventId),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RootCause {
+    Boot,
+    /// Ring buffer overwrote events that had not been drained yet
+    Overflow,
+    /// CPU exception or external interrupt
+    Hardware,
+    /// Kernel panic on a core that had not recorded any event yet
+    Panic,
+    /// Misuse caught by a kernel check outside any causal context
+    Misuse,
+    /// Kernel work done outside any causal context, with nothing to attribute it to
+    Unattributed,
+}
+
+/// Misuse caught by the hardened frame allocator, carried in `FrameFault` events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameFaultReason {
+    /// Freed a frame that was already free
+    DoubleFree = 0,
+    /// Freed a frame the allocator never managed
+    ForeignFree = 1,
+    /// Free frame written to after it was freed
+    UseAfterFree = 2,
+}
+
+impl FrameFaultReason {
+    pub const fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::DoubleFree),
+            1 => Some(Self::ForeignFree),
+            2 => Some(Self::UseAfterFree),
+            _ => None,
+        }
+    }
+}
+
+/// Boot-time memory handed to the frame allocator, carried in `MemoryReclaim` events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ReclaimedRegion {
+    /// Memory the bootloader used for its own data and responses
+    Bootloader = 0,
+    /// Memory holding ACPI tables
+    AcpiReclaimable = 1,
+}
+
+impl ReclaimedRegion {
+    pub const fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Bootloader),
+            1 => Some(Self::AcpiReclaimable),
+            _ => None,
+        }
+    }
+}
+
+event_schema! {
+    data {
+        None = 0,
+        /// Inclusive range of sequence numbers on `core` that were overwritten before being drained
+        Loss = 1 {
+            core: u16,
+            first_sequence: u64,
+            last_sequence: u64,
+        },
+        /// CPU exception state; `error_code` is 0 for vectors without one and `cr2` is only
+        /// meaningful for page faults
+        Exception = 2 {
+            vector: u8,
+            error_code: u64,
+            rip: u64,
+            cr2: u64,
+        },
+        /// Interrupt vector and the instruction pointer it interrupted
+        Irq = 3 {
+            vector: u8,
+            rip: u64,
+        },
+        /// Physical address of a page frame
+        Frame = 4 {
+            phys: u64,
+        },
+        /// Virtual to physical page mapping and its page table entry flags
+        Mapping = 5 {
+            virt: u64,
+            phys: u64,
+            flags: u64,
+        },
+        /// Slab object and the id of the cache it belongs to
+        Object = 6 {
+            cache: u16,
+            addr: u64,
+        },
+        /// Physical frame the frame allocator caught being misused and the `FrameFaultReason` code
+        FrameFault = 7 {
+            phys: u64,
+            reason: u8,
+        },
+        /// Frames of a `ReclaimedRegion` kind handed to the frame allocator
+        Reclaim = 8 {
+            region: u8,
+            frames: u64,
+        },
+    }
+    kinds {
+        Boot = 0 => None,
+        Loss = 1 => Loss,
+        Exception = 2 => Exception,
+        IrqEntry = 3 => Irq,
+        IrqExit = 4 => Irq,
+        Panic = 5 => None,
+        FrameAlloc = 6 => Frame,
+        FrameFree = 7 => Frame,
+        PageMap = 8 => Mapping,
+        PageUnmap = 9 => Mapping,
+        SlabAlloc = 10 => Object,
+        SlabFree = 11 => Object,
+        FrameFault = 12 => FrameFault,
+        MemoryReclaim = 13 => Reclaim,
+    }
+}
+
+// cpu core + sequence number provide a globally unique EventId
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventId {
+    core: u16,
+    sequence: u64,
+}
+
+impl EventId {
+    pub const fn new(core: u16, sequence: u64) -> Self {
+        Self { core, sequence }
+    }
+
+    pub const fn core(&self) -> u16 {
+        self.core
+    }
+
+    pub const fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub id: EventId,
+    /// TSC cycles at record time. Advisory only: never use it to order events.
+    pub timestamp: u64,
+    pub kind: EventKind,
+    pub cause: Cause,
+    pub data: EventData,
+}
+
+const _: () = assert!(size_of::<Event>() <= MAX_EVENT_SIZE, "Event exceeds MAX_EVENT_SIZE");
@@ -0,0 +1,422 @@
+//! Versioned, fixed-size little-endian wire format for causality events.
+//!
+//! A stream starts with a header and continues with fixed-size records. Every record is
+//! framed by a sync word and protected by a CRC-32 so a host collector can resync when
+//! records share the serial line with plain text output:
+//!
+//! ```text
+//! header (24 bytes):
+//!   0  magic             [u8; 4] = "CTRC"
+//!   4  version           u16
+//!   6  header_len        u16
+//!   8  record_len        u16
+//!   10 flags             u16    HEADER_FLAG_*
+//!   12 cycles_per_ns_q32 u64    TSC cycles per ns, Q32.32, 0 if uncalibrated
+//!   20 crc32             u32    over bytes 0..20
+//!
+//! record (72 bytes):
+//!   0  sync        u16    = RECORD_SYNC
+//!   2  record_type u8
+//!   3  payload_len u8
+//!   4  payload     [u8; payload_len]
+//!   68 crc32       u32    over bytes 2..68
+//!
+//! event payload (64 bytes):
+//!   0  sequence        u64
+//!   8  cause_sequence  u64    parent sequence, 0 for roots
+//!   16 timestamp       u64    TSC cycles, advisory
+//!   24 core            u16
+//!   26 kind            u16
+//!   28 cause_core      u16    parent core, or RootCause code for roots
+//!   30 cause_tag       u8
+//!   31 data_tag        u8
+//!   32 data            [u8; EVENT_DATA_LEN]   layout given by DATA_DESCRIPTORS
+//! ```
+//!
+//! Record types:
+//!
+//! - `RECORD_TYPE_EVENT`: streamed by the drain, in increasing sequence order per core.
+//! - `RECORD_TYPE_DUMPED_EVENT`: re-sent by a panic dump, newest first per core. These may
+//!   repeat events that were already streamed. The dump is preceded by a header with
+//!   `HEADER_FLAG_PANIC_DUMP` set; any other header starts a new boot, whose sequences
+//!   restart from zero.
+//!
+//! Timestamps are advisory: decoders must not derive event order from them.
+
+pub use crate::schema::EVENT_DATA_LEN;
+use crate::types::{Cause, DataTag, Event, EventData, EventId, EventKind, RootCause};
+
+pub const STREAM_MAGIC: [u8; 4] = *b"CTRC";
+/// Bumped whenever an encoding changes, new RootCause codes included, so older decoders
+/// reject the stream instead of misreading it.
+pub const VERSION: u16 = 4;
+
+pub const HEADER_LEN: usize = 24;
+pub const RECORD_LEN: usize = 72;
+pub const RECORD_SYNC: u16 = 0xec5a;
+
+pub const RECORD_TYPE_EVENT: u8 = 1;
+pub const RECORD_TYPE_DUMPED_EVENT: u8 = 2;
+
+pub const EVENT_PAYLOAD_LEN: usize = 64;
+
+const RECORD_PAYLOAD_OFFSET: usize = 4;
+const RECORD_CRC_OFFSET: usize = RECORD_PAYLOAD_OFFSET + EVENT_PAYLOAD_LEN;
+const HEADER_CRC_OFFSET: usize = 20;
+const EVENT_DATA_OFFSET: usize = 32;
+
+/// The TSC is invariant, so timestamps from different cores share a time base.
+pub const HEADER_FLAG_INVARIANT_TSC: u16 = 1 << 0;
+/// The header opens a panic dump that continues the stream of the same boot rather than
+/// a new boot, so sequence numbers carry on from the preceding header.
+pub const HEADER_FLAG_PANIC_DUMP: u16 = 1 << 1;
+
+pub const CAUSE_TAG_ROOT: u8 = 0;
+pub const CAUSE_TAG_CAUSED_BY: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// HEADER_FLAG_* bits
+    pub flags: u16,
+    /// TSC cycles per nanosecond as unsigned Q32.32 fixed point, 0 if uncalibrated.
+    pub cycles_per_ns_q32: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    BadMagic,
+    BadSync,
+    BadChecksum,
+    BadLength,
+    UnsupportedVersion(u16),
+    UnknownRecordType(u8),
+    UnknownKind(u16),
+    UnknownRootCause(u16),
+    UnknownCauseTag(u8),
+    UnknownDataTag(u8),
+    /// The payload tag is not the one the event kind declares.
+    DataMismatch { kind: u16, tag: u8 },
+}
+
+pub fn encode_header(header: &Header, buf: &mut [u8; HEADER_LEN]) {
+    buf[0..4].copy_from_slice(&STREAM_MAGIC);
+    put_u16(buf, 4, VERSION);
+    put_u16(buf, 6, HEADER_LEN as u16);
+    put_u16(buf, 8, RECORD_LEN as u16);
+    put_u16(buf, 10, header.flags);
+    put_u64(buf, 12, header.cycles_per_ns_q32);
+
+    let crc = crc32(&buf[..HEADER_CRC_OFFSET]);
+    put_u32(buf, HEADER_CRC_OFFSET, crc);
+}
+
+/// Encode `event` as a record of `record_type`, one of the RECORD_TYPE_*EVENT types.
+pub fn encode_event(event: &Event, record_type: u8, buf: &mut [u8; RECORD_LEN]) {
+    buf.fill(0);
+    put_u16(buf, 0, RECORD_SYNC);
+    buf[2] = record_type;
+    buf[3] = EVENT_PAYLOAD_LEN as u8;
+
+    let payload = &mut buf[RECORD_PAYLOAD_OFFSET..RECORD_CRC_OFFSET];
+    put_u64(payload, 0, event.id.sequence());
+    put_u64(payload, 16, event.timestamp);
+    put_u16(payload, 24, event.id.core());
+    put_u16(payload, 26, event.kind.code());
+
+    match event.cause {
+        Cause::Root(root) => {
+            put_u16(payload, 28, root_cause_code(root));
+            payload[30] = CAUSE_TAG_ROOT;
+        }
+        Cause::CausedBy(parent) => {
+            put_u64(payload, 8, parent.sequence());
+            put_u16(payload, 28, parent.core());
+            payload[30] = CAUSE_TAG_CAUSED_BY;
+        }
+    }
+
+    payload[31] = event.data.tag() as u8;
+    let data = (&mut payload[EVENT_DATA_OFFSET..EVENT_DATA_OFFSET + EVENT_DATA_LEN])
+        .try_into()
+        .expect("payload data area is EVENT_DATA_LEN bytes");
+    event.data.encode(data);
+
+    let crc = crc32(&buf[2..RECORD_CRC_OFFSET]);
+    put_u32(buf, RECORD_CRC_OFFSET, crc);
+}
+
+pub fn decode_header(buf: &[u8; HEADER_LEN]) -> Result<Header, DecodeError> {
+    if buf[0..4] != STREAM_MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    if get_u32(buf, HEADER_CRC_OFFSET) != crc32(&buf[..HEADER_CRC_OFFSET]) {
+        return Err(DecodeError::BadChecksum);
+    }
+
+    let version = get_u16(buf, 4);
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let header_len = get_u16(buf, 6) as usize;
+    let record_len = get_u16(buf, 8) as usize;
+    if header_len != HEADER_LEN || record_len != RECORD_LEN {
+        return Err(DecodeError::BadLength);
+    }
+
+    Ok(Header {
+        flags: get_u16(buf, 10),
+        cycles_per_ns_q32: get_u64(buf, 12),
+    })
+}
+
+/// Decode an event record, returning its record type alongside the event.
+pub fn decode_event(buf: &[u8; RECORD_LEN]) -> Result<(u8, Event), DecodeError> {
+    if get_u16(buf, 0) != RECORD_SYNC {
+        return Err(DecodeError::BadSync);
+    }
+    if get_u32(buf, RECORD_CRC_OFFSET) != crc32(&buf[2..RECORD_CRC_OFFSET]) {
+        return Err(DecodeError::BadChecksum);
+    }
+    let record_type = buf[2];
+    if record_type != RECORD_TYPE_EVENT && record_type != RECORD_TYPE_DUMPED_EVENT {
+        return Err(DecodeError::UnknownRecordType(record_type));
+    }
+    if buf[3] as usize != EVENT_PAYLOAD_LEN {
+        return Err(DecodeError::BadLength);
+    }
+
+    let payload = &buf[RECORD_PAYLOAD_OFFSET..RECORD_CRC_OFFSET];
+    let id = EventId::new(get_u16(payload, 24), get_u64(payload, 0));
+    let timestamp = get_u64(payload, 16);
+
+    let kind_code = get_u16(payload, 26);
+    let kind = EventKind::from_code(kind_code).ok_or(DecodeError::UnknownKind(kind_code))?;
+
+    let cause = match payload[30] {
+        CAUSE_TAG_ROOT => {
+            let root_code = get_u16(payload, 28);
+            Cause::Root(root_cause_from_code(root_code).ok_or(DecodeError::UnknownRootCause(root_code))?)
+        }
+        CAUSE_TAG_CAUSED_BY => Cause::CausedBy(EventId::new(get_u16(payload, 28), get_u64(payload, 8))),
+        tag => return Err(DecodeError::UnknownCauseTag(tag)),
+    };
+
+    let tag = DataTag::from_u8(payload[31]).ok_or(DecodeError::UnknownDataTag(payload[31]))?;
+    if tag != kind.data_tag() {
+        return Err(DecodeError::DataMismatch { kind: kind_code, tag: tag as u8 });
+    }
+    let data = payload[EVENT_DATA_OFFSET..EVENT_DATA_OFFSET + EVENT_DATA_LEN]
+        .try_into()
+        .expect("payload data area is EVENT_DATA_LEN bytes");
+    let data = EventData::decode(tag, data);
+
+    let event = Event {
+        id,
+        timestamp,
+        kind,
+        cause,
+        data,
+    };
+    Ok((record_type, event))
+}
+
+pub const fn root_cause_code(root: RootCause) -> u16 {
+    match root {
+        RootCause::Boot => 0,
+        RootCause::Overflow => 1,
+        RootCause::Hardware => 2,
+        RootCause::Panic => 3,
+        RootCause::Misuse => 4,
+        RootCause::Unattributed => 5,
+    }
+}
+
+pub const fn root_cause_from_code(code: u16) -> Option<RootCause> {
+    match code {
+        0 => Some(RootCause::Boot),
+        1 => Some(RootCause::Overflow),
+        2 => Some(RootCause::Hardware),
+        3 => Some(RootCause::Panic),
+        4 => Some(RootCause::Misuse),
+        5 => Some(RootCause::Unattributed),
+        _ => None,
+    }
+}
+
+/// CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320).
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut [u8], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn get_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(event: &Event, record_type: u8) -> [u8; RECORD_LEN] {
+        let mut buf = [0u8; RECORD_LEN];
+        encode_event(event, record_type, &mut buf);
+        buf
+    }
+
+    /// Re-seal a record after editing its payload, as a well-framed but invalid record.
+    fn reseal(buf: &mut [u8; RECORD_LEN]) {
+        let crc = crc32(&buf[2..RECORD_CRC_OFFSET]);
+        put_u32(buf, RECORD_CRC_OFFSET, crc);
+    }
+
+    fn exception() -> Event {
+        Event {
+            id: EventId::new(3, 0x1_0000_0007),
+            timestamp: 0xdead_beef_cafe,
+            kind: EventKind::Exception,
+            cause: Cause::CausedBy(EventId::new(1, 42)),
+            data: EventData::Exception {
+                vector: 14,
+                error_code: 2,
+                rip: 0xffff_ffff_8000_1234,
+                cr2: 0x1000,
+            },
+        }
+    }
+
+    #[test]
+    fn crc32_matches_ieee_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let header = Header {
+            flags: HEADER_FLAG_INVARIANT_TSC | HEADER_FLAG_PANIC_DUMP,
+            cycles_per_ns_q32: 3 << 32 | 0x8000_0000,
+        };
+        let mut buf = [0u8; HEADER_LEN];
+        encode_header(&header, &mut buf);
+
+        assert_eq!(buf[..4], STREAM_MAGIC);
+        assert_eq!(decode_header(&buf), Ok(header));
+    }
+
+    #[test]
+    fn header_rejects_corruption_and_other_versions() {
+        let mut buf = [0u8; HEADER_LEN];
+        encode_header(&Header { flags: 0, cycles_per_ns_q32: 0 }, &mut buf);
+
+        let mut corrupted = buf;
+        corrupted[12] ^= 1;
+        assert_eq!(decode_header(&corrupted), Err(DecodeError::BadChecksum));
+
+        // Version 3 predates the Misuse and Unattributed root causes.
+        for version in [3, VERSION + 1] {
+            let mut other = buf;
+            put_u16(&mut other, 4, version);
+            let crc = crc32(&other[..HEADER_CRC_OFFSET]);
+            put_u32(&mut other, HEADER_CRC_OFFSET, crc);
+            assert_eq!(decode_header(&other), Err(DecodeError::UnsupportedVersion(version)));
+        }
+    }
+
+    #[test]
+    fn event_round_trips() {
+        let event = exception();
+        assert_eq!(decode_event(&encoded(&event, RECORD_TYPE_EVENT)), Ok((RECORD_TYPE_EVENT, event)));
+
+        let root = Event {
+            id: EventId::new(0, 0),
+            timestamp: 1,
+            kind: EventKind::Loss,
+            cause: Cause::Root(RootCause::Overflow),
+            data: EventData::Loss { core: 0, first_sequence: 5, last_sequence: 9 },
+        };
+        let buf = encoded(&root, RECORD_TYPE_DUMPED_EVENT);
+        assert_eq!(decode_event(&buf), Ok((RECORD_TYPE_DUMPED_EVENT, root)));
+    }
+
+    #[test]
+    fn every_root_cause_round_trips() {
+        let roots = [
+            RootCause::Boot,
+            RootCause::Overflow,
+            RootCause::Hardware,
+            RootCause::Panic,
+            RootCause::Misuse,
+            RootCause::Unattributed,
+        ];
+        for root in roots {
+            assert_eq!(root_cause_from_code(root_cause_code(root)), Some(root));
+        }
+        assert_eq!(root_cause_from_code(6), None);
+    }
+
+    #[test]
+    fn corrupted_record_fails_checksum() {
+        let mut buf = encoded(&exception(), RECORD_TYPE_EVENT);
+        buf[RECORD_PAYLOAD_OFFSET + EVENT_DATA_OFFSET] ^= 0x40;
+        assert_eq!(decode_event(&buf), Err(DecodeError::BadChecksum));
+
+        let mut buf = encoded(&exception(), RECORD_TYPE_EVENT);
+        buf[0] ^= 0xff;
+        assert_eq!(decode_event(&buf), Err(DecodeError::BadSync));
+    }
+
+    #[test]
+    fn well_framed_invalid_records_are_rejected() {
+        let mut buf = encoded(&exception(), RECORD_TYPE_EVENT);
+        buf[2] = 9;
+        reseal(&mut buf);
+        assert_eq!(decode_event(&buf), Err(DecodeError::UnknownRecordType(9)));
+
+        let mut buf = encoded(&exception(), RECORD_TYPE_EVENT);
+        put_u16(&mut buf[RECORD_PAYLOAD_OFFSET..], 26, 0xffff);
+        reseal(&mut buf);
+        assert_eq!(decode_event(&buf), Err(DecodeError::UnknownKind(0xffff)));
+
+        let mut buf = encoded(&exception(), RECORD_TYPE_EVENT);
+        buf[RECORD_PAYLOAD_OFFSET + 31] = DataTag::Irq as u8;
+        reseal(&mut buf);
+        assert_eq!(
+            decode_event(&buf),
+            Err(DecodeError::DataMismatch {
+                kind: EventKind::Exception.code(),
+                tag: DataTag::Irq as u8
+            })
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Fixed ACPI Description Table: reset, soft-off and the ACPI PM timer.
+
+use crate::sync::SpinLock;
+
+use super::{AcpiError, GenericAddress, SdtHeader, u32_at, u64_at, valid_table_at};
+
+pub const SIGNATURE: [u8; 4] = *b"FACP";
+
+/// The PM timer always runs at this rate.
+pub const PM_TIMER_HZ: u64 = 3_579_545;
+
+const OFFSET_DSDT: usize = 40;
+const OFFSET_PM1A_CNT_BLK: usize = 64;
+const OFFSET_PM1B_CNT_BLK: usize = 68;
+const OFFSET_PM_TMR_BLK: usize = 76;
+const OFFSET_PM1_CNT_LEN: usize = 89;
+const OFFSET_PM_TMR_LEN: usize = 91;
+const OFFSET_FLAGS: usize = 112;
+const OFFSET_RESET_REG: usize = 116;
+const OFFSET_RESET_VALUE: usize = 128;
+const OFFSET_X_DSDT: usize = 140;
+const OFFSET_X_PM1A_CNT_BLK: usize = 172;
+const OFFSET_X_PM1B_CNT_BLK: usize = 184;
+const OFFSET_X_PM_TMR_BLK: usize = 208;
+
+const FLAG_TMR_VAL_EXT: u32 = 1 << 8;
+const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+
+const PM1_SLP_TYP_SHIFT: u32 = 10;
+const PM1_SLP_EN: u32 = 1 << 13;
+
+const AML_S5_NAME: &[u8; 4] = b"_S5_";
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_PKG_LENGTH_BYTES_SHIFT: u8 = 6;
+
+/// Copy kept across acpi::release(), when the table itself goes away.
+static RETAINED: SpinLock<Option<Fadt>> = SpinLock::new(None);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Fadt {
+    /// Physical address of the DSDT.
+    pub dsdt: u64,
+    pub pm1a_control: Option<GenericAddress>,
+    pub pm1b_control: Option<GenericAddress>,
+    pub pm_timer: Option<GenericAddress>,
+    /// The PM timer counts 32 bits instead of 24.
+    pub pm_timer_32bit: bool,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+    /// SLP_TYPa and SLP_TYPb for S5, from the DSDT's `\_S5_` object.
+    pub s5_sleep_types: Option<(u8, u8)>,
+}
+
+/// The FADT, or None if ACPI has none. Still available after acpi::release().
+pub fn fadt() -> Option<Fadt> {
+    super::find_table(&SIGNATURE).map(parse).or_else(|| *RETAINED.lock())
+}
+
+/// Keep a copy of the FADT for after acpi::release().
+pub(super) fn retain() {
+    *RETAINED.lock() = fadt();
+}
+
+fn parse(table: &SdtHeader) -> Fadt {
+    let bytes = table.bytes();
+    let u8_at = |offset: usize| bytes.get(offset).copied().unwrap_or(0);
+    let u32_or_0 = |offset| u32_at(bytes, offset).unwrap_or(0);
+    // ACPI 2.0+ extended fields win over the legacy 32-bit ones when present.
+    let extended_or_port = |extended, port, len| {
+        GenericAddress::parse(bytes, extended).or(GenericAddress::io_port(u32_or_0(port), len))
+    };
+
+    let flags = u32_or_0(OFFSET_FLAGS);
+    let dsdt = match u64_at(bytes, OFFSET_X_DSDT) {
+        Some(dsdt) if dsdt != 0 => dsdt,
+        _ => u32_or_0(OFFSET_DSDT) as u64,
+    };
+    let s5_sleep_types = valid_table_at(dsdt).and_then(|dsdt| s5_sleep_types(dsdt.body()));
+    let reset_register = match flags & FLAG_RESET_REG_SUP {
+        0 => None,
+        _ => GenericAddress::parse(bytes, OFFSET_RESET_REG),
+    };
+
+    Fadt {
+        dsdt,
+        pm1a_control: extended_or_port(OFFSET_X_PM1A_CNT_BLK, OFFSET_PM1A_CNT_BLK, u8_at(OFFSET_PM1_CNT_LEN)),
+        pm1b_control: extended_or_port(OFFSET_X_PM1B_CNT_BLK, OFFSET_PM1B_CNT_BLK, u8_at(OFFSET_PM1_CNT_LEN)),
+        pm_timer: extended_or_port(OFFSET_X_PM_TMR_BLK, OFFSET_PM_TMR_BLK, u8_at(OFFSET_PM_TMR_LEN)),
+        pm_timer_32bit: flags & FLAG_TMR_VAL_EXT != 0,
+        reset_register,
+        reset_value: u8_at(OFFSET_RESET_VALUE),
+        s5_sleep_types,
+    }
+}
+
+impl Fadt {
+    /// Bits of the PM timer count that are significant.
+    pub fn pm_timer_mask(&self) -> u32 {
+        if self.pm_timer_32bit { u32::MAX } else { 0x00ff_ffff }
+    }
+
+    /// Current PM timer count; wraps at pm_timer_mask() ticks of PM_TIMER_HZ.
+    pub fn read_pm_timer(&self) -> Result<u32, AcpiError> {
+        let count = self.pm_timer.ok_or(AcpiError::Unsupported)?.read()?;
+        Ok(count & self.pm_timer_mask())
+    }
+
+    /// Reset the machine through the FADT reset register. Returns only if the
+    /// register is absent or the write did not take effect.
+    #[allow(dead_code, reason = "kept for the shutdown path; the kernel never stops yet")]
+    pub fn reset(&self) -> Result<(), AcpiError> {
+        let reset_register = self.reset_register.ok_or(AcpiError::Unsupported)?;
+        reset_register.write(self.reset_value as u32)
+    }
+
+    /// Enter S5 (soft-off) with the sleep types from the DSDT's `\_S5_` object.
+    /// Returns only if S5 is not described or the write did not take effect.
+    #[allow(dead_code, reason = "kept for the shutdown path; the kernel never stops yet")]
+    pub fn poweroff(&self) -> Result<(), AcpiError> {
+        let (sleep_type_a, sleep_type_b) = self.s5_sleep_types.ok_or(AcpiError::Unsupported)?;
+        let pm1a_control = self.pm1a_control.ok_or(AcpiError::Unsupported)?;
+
+        pm1a_control.write(((sleep_type_a as u32) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN)?;
+        if let Some(pm1b_control) = self.pm1b_control {
+            pm1b_control.write(((sleep_type_b as u32) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN)?;
+        }
+        Ok(())
+    }
+}
+
+/// SLP_TYPa and SLP_TYPb from `Name (_S5, Package () { a, b, ... })` in AML. This is a
+/// pattern match on the encoded object rather than an AML interpreter, which is enough
+/// for the static `\_S5_` package firmware ships.
+fn s5_sleep_types(aml: &[u8]) -> Option<(u8, u8)> {
+    let name = aml.windows(AML_S5_NAME.len()).position(|window| window == AML_S5_NAME)?;
+    let mut rest = aml.get(name + AML_S5_NAME.len()..)?;
+
+    if *rest.first()? != AML_PACKAGE_OP {
+        return None;
+    }
+    let pkg_length_bytes = (rest.get(1)? >> AML_PKG_LENGTH_BYTES_SHIFT) as usize + 1;
+    // PackageOp, PkgLength, NumElements
+    rest = rest.get(1 + pkg_length_bytes + 1..)?;
+
+    let (sleep_type_a, rest) = aml_integer(rest)?;
+    let (sleep_type_b, _) = aml_integer(rest)?;
+    Some((sleep_type_a, sleep_type_b))
+}
+
+fn aml_integer(aml: &[u8]) -> Option<(u8, &[u8])> {
+    match *aml.first()? {
+        AML_ZERO_OP => Some((0, &aml[1..])),
+        AML_ONE_OP => Some((1, &aml[1..])),
+        AML_BYTE_PREFIX => Some((*aml.get(1)?, &aml[2..])),
+        _ => None,
+    }
+}
@@ -0,0 +1,50 @@
+//! High Precision Event Timer description table.
+
+#![allow(dead_code, reason = "the descriptor is decoded in full; boot only prints part of it")]
+
+use super::{GenericAddress, u16_at, u32_at};
+
+pub const SIGNATURE: [u8; 4] = *b"HPET";
+
+const OFFSET_EVENT_TIMER_BLOCK_ID: usize = 36;
+const OFFSET_BASE_ADDRESS: usize = 40;
+const OFFSET_HPET_NUMBER: usize = 52;
+const OFFSET_MINIMUM_TICK: usize = 53;
+
+const BLOCK_ID_REVISION_MASK: u32 = 0xff;
+const BLOCK_ID_COMPARATORS_SHIFT: u32 = 8;
+const BLOCK_ID_COMPARATORS_MASK: u32 = 0x1f;
+const BLOCK_ID_COUNTER_64BIT: u32 = 1 << 13;
+const BLOCK_ID_LEGACY_REPLACEMENT: u32 = 1 << 15;
+const BLOCK_ID_VENDOR_SHIFT: u32 = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    /// Register block, always in memory space.
+    pub base_address: GenericAddress,
+    pub number: u8,
+    /// Minimum main counter ticks between periodic interrupts.
+    pub minimum_tick: u16,
+}
+
+/// The HPET, or None if ACPI does not describe one.
+pub fn hpet() -> Option<Hpet> {
+    let bytes = super::find_table(&SIGNATURE)?.bytes();
+    let block_id = u32_at(bytes, OFFSET_EVENT_TIMER_BLOCK_ID)?;
+
+    Some(Hpet {
+        hardware_revision: (block_id & BLOCK_ID_REVISION_MASK) as u8,
+        comparators: ((block_id >> BLOCK_ID_COMPARATORS_SHIFT) & BLOCK_ID_COMPARATORS_MASK) as u8 + 1,
+        counter_64bit: block_id & BLOCK_ID_COUNTER_64BIT != 0,
+        legacy_replacement: block_id & BLOCK_ID_LEGACY_REPLACEMENT != 0,
+        pci_vendor_id: (block_id >> BLOCK_ID_VENDOR_SHIFT) as u16,
+        base_address: GenericAddress::parse(bytes, OFFSET_BASE_ADDRESS)?,
+        number: *bytes.get(OFFSET_HPET_NUMBER)?,
+        minimum_tick: u16_at(bytes, OFFSET_MINIMUM_TICK)?,
+    })
+}
@@ -0,0 +1,105 @@
+//! Multiple APIC Description Table: interrupt controllers and legacy IRQ overrides.
+
+#![allow(dead_code, reason = "entries are decoded in full; only I/O APICs and overrides are consumed so far")]
+
+use super::{Subtables, u16_at, u32_at};
+
+pub const SIGNATURE: [u8; 4] = *b"APIC";
+
+/// Header, local APIC address and flags precede the entries.
+const ENTRIES_OFFSET: usize = 44;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+/// MPS INTI flags of an interrupt source override.
+const POLARITY_MASK: u16 = 0b11;
+const POLARITY_ACTIVE_LOW: u16 = 0b11;
+const TRIGGER_MASK: u16 = 0b11 << 2;
+const TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+#[derive(Clone, Copy, Debug)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    /// ISA `source` IRQ is wired to `gsi` instead of the identity-mapped one.
+    InterruptSourceOverride {
+        source: u8,
+        gsi: u32,
+        active_low: bool,
+        level_triggered: bool,
+    },
+    LocalX2Apic {
+        x2apic_id: u32,
+        flags: u32,
+        processor_uid: u32,
+    },
+    /// Entry type this parser does not decode.
+    Other(u8),
+}
+
+pub struct Entries {
+    subtables: Subtables,
+}
+
+impl Iterator for Entries {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<MadtEntry> {
+        let (kind, entry) = self.subtables.next()?;
+        Some(parse_entry(kind, entry).unwrap_or(MadtEntry::Other(kind)))
+    }
+}
+
+/// None if the entry is too short for its type.
+fn parse_entry(kind: u8, entry: &[u8]) -> Option<MadtEntry> {
+    Some(match kind {
+        ENTRY_LOCAL_APIC => MadtEntry::LocalApic {
+            processor_id: *entry.get(2)?,
+            apic_id: *entry.get(3)?,
+            flags: u32_at(entry, 4)?,
+        },
+        ENTRY_IO_APIC => MadtEntry::IoApic {
+            id: *entry.get(2)?,
+            address: u32_at(entry, 4)?,
+            gsi_base: u32_at(entry, 8)?,
+        },
+        ENTRY_INTERRUPT_SOURCE_OVERRIDE => {
+            let flags = u16_at(entry, 8)?;
+            MadtEntry::InterruptSourceOverride {
+                source: *entry.get(3)?,
+                gsi: u32_at(entry, 4)?,
+                active_low: flags & POLARITY_MASK == POLARITY_ACTIVE_LOW,
+                level_triggered: flags & TRIGGER_MASK == TRIGGER_LEVEL,
+            }
+        }
+        ENTRY_LOCAL_X2APIC => MadtEntry::LocalX2Apic {
+            x2apic_id: u32_at(entry, 4)?,
+            flags: u32_at(entry, 8)?,
+            processor_uid: u32_at(entry, 12)?,
+        },
+        kind => MadtEntry::Other(kind),
+    })
+}
+
+/// Entries of the MADT, empty if ACPI has no MADT.
+pub fn entries() -> Entries {
+    let bytes = match super::find_table(&SIGNATURE) {
+        Some(table) => table.bytes().get(ENTRIES_OFFSET..).unwrap_or(&[]),
+        None => &[],
+    };
+    Entries {
+        subtables: Subtables::new(bytes),
+    }
+}
+
@@ -0,0 +1,60 @@
+//! PCI Express memory-mapped configuration space (ECAM) regions.
+
+#![allow(dead_code, reason = "config_address() is for the PCI driver, which does not exist yet")]
+
+use super::{u16_at, u64_at};
+
+pub const SIGNATURE: [u8; 4] = *b"MCFG";
+
+/// Header and 8 reserved bytes precede the allocations.
+const ENTRIES_OFFSET: usize = 44;
+const ENTRY_LEN: usize = 16;
+
+const BUS_SHIFT: u64 = 20;
+const DEVICE_SHIFT: u64 = 15;
+const FUNCTION_SHIFT: u64 = 12;
+const MAX_DEVICE: u8 = 31;
+const MAX_FUNCTION: u8 = 7;
+
+/// Configuration space of buses `start_bus..=end_bus` in one PCI segment group.
+#[derive(Clone, Copy, Debug)]
+pub struct EcamRegion {
+    /// Physical address of bus 0's configuration space, even if start_bus is not 0.
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl EcamRegion {
+    /// Physical address of the 4 KiB configuration space of a function, or None if it
+    /// is outside this region.
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> Option<u64> {
+        if bus < self.start_bus || bus > self.end_bus || device > MAX_DEVICE || function > MAX_FUNCTION {
+            return None;
+        }
+        Some(
+            self.base
+                + ((bus as u64) << BUS_SHIFT)
+                + ((device as u64) << DEVICE_SHIFT)
+                + ((function as u64) << FUNCTION_SHIFT),
+        )
+    }
+}
+
+/// ECAM regions, empty if ACPI has no MCFG.
+pub fn regions() -> impl Iterator<Item = EcamRegion> {
+    let bytes = match super::find_table(&SIGNATURE) {
+        Some(table) => table.bytes().get(ENTRIES_OFFSET..).unwrap_or(&[]),
+        None => &[],
+    };
+
+    bytes.chunks_exact(ENTRY_LEN).filter_map(|entry| {
+        Some(EcamRegion {
+            base: u64_at(entry, 0)?,
+            segment: u16_at(entry, 8)?,
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+    })
+}
@@ -0,0 +1,454 @@
+//! ACPI table discovery and typed access to the tables the kernel uses.
+//!
+//! Tables are read in place through the higher-half direct map; nothing is copied.
+//! Memory-space registers are MMIO, which the direct map does not cover, so they are
+//! mapped uncached into a window at MMIO_VIRT on first access.
+//! Every table is checksummed before it is handed out, and a table that fails is
+//! treated as absent.
+//!
+//! Tables usually live in ACPI-reclaimable memory. Once release() has run, before that
+//! memory is reclaimed, no table is handed out any more; only the FADT is kept.
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+pub mod slit;
+pub mod srat;
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::arch::x86_64::port::{inb, inl, inw, outb, outl, outw};
+use crate::mm::address_space;
+use crate::mm::page::{self, MapError, PageFlags};
+use crate::sync::SpinLock;
+use crate::{print, println};
+
+use self::srat::SratEntry;
+
+const RSDP_SIGNATURE: [u8; 8] = *b"RSD PTR ";
+const RSDT_SIGNATURE: [u8; 4] = *b"RSDT";
+const XSDT_SIGNATURE: [u8; 4] = *b"XSDT";
+/// RSDP revision from which the XSDT address and extended checksum are present.
+const RSDP_REVISION_XSDT: u8 = 2;
+/// Bytes covered by the ACPI 1.0 RSDP checksum.
+const RSDP_V1_LEN: usize = 20;
+
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+const ADDRESS_SPACE_IO: u8 = 1;
+
+const PAGE_SIZE: u64 = 4096;
+const MMIO_VIRT: u64 = 0xffffffffa0010000;
+const MMIO_PAGES: usize = 16;
+
+static HHDM_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Physical address of the RSDT or XSDT, 0 before init().
+static ROOT_TABLE: AtomicU64 = AtomicU64::new(0);
+static RELEASED: AtomicBool = AtomicBool::new(false);
+/// Physical page mapped at each page of the MMIO window.
+static MMIO_MAPPINGS: SpinLock<[Option<u64>; MMIO_PAGES]> = SpinLock::new([None; MMIO_PAGES]);
+
+#[derive(Debug)]
+pub enum AcpiError {
+    BadRsdp,
+    BadChecksum,
+    BadRootTable,
+    /// The firmware does not provide what the operation needs.
+    Unsupported,
+    /// Every page of the MMIO window is in use.
+    MmioWindowFull,
+    Map(MapError),
+}
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // Revision 2 and later.
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+/// Header shared by every system description table.
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// The whole table, header included, so spec offsets index it directly.
+    pub fn bytes(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self as *const SdtHeader as *const u8, self.length as usize) }
+    }
+
+    /// Table contents after the header.
+    pub fn body(&self) -> &'static [u8] {
+        &self.bytes()[size_of::<SdtHeader>()..]
+    }
+
+    fn is_valid(&self) -> bool {
+        (self.length as usize) >= size_of::<SdtHeader>() && checksum_ok(self.bytes())
+    }
+}
+
+/// ACPI Generic Address Structure: a register in memory or I/O port space.
+#[derive(Clone, Copy, Debug)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    /// Parse the structure at `offset`, or None if the table is too short or the
+    /// register is absent.
+    fn parse(bytes: &[u8], offset: usize) -> Option<Self> {
+        let address = u64_at(bytes, offset + 4)?;
+        if address == 0 {
+            return None;
+        }
+        Some(Self {
+            address_space: *bytes.get(offset)?,
+            bit_width: bytes[offset + 1],
+            access_size: bytes[offset + 3],
+            address,
+        })
+    }
+
+    /// A legacy fixed-hardware I/O port block of `len` bytes.
+    fn io_port(port: u32, len: u8) -> Option<Self> {
+        if port == 0 {
+            return None;
+        }
+        Some(Self {
+            address_space: ADDRESS_SPACE_IO,
+            bit_width: len * 8,
+            access_size: 0,
+            address: port as u64,
+        })
+    }
+
+    /// Register width in bits, from the explicit width or the access size.
+    fn width(&self) -> u8 {
+        match (self.bit_width, self.access_size) {
+            (8 | 16 | 32, _) => self.bit_width,
+            (_, 1) => 8,
+            (_, 2) => 16,
+            _ => 32,
+        }
+    }
+
+    pub fn read(&self) -> Result<u32, AcpiError> {
+        match self.address_space {
+            ADDRESS_SPACE_IO => {
+                let port = self.address as u16;
+                Ok(match self.width() {
+                    8 => inb(port) as u32,
+                    16 => inw(port) as u32,
+                    _ => inl(port),
+                })
+            }
+            ADDRESS_SPACE_MEMORY => {
+                let virt = map_mmio(self.address)?;
+                Ok(unsafe {
+                    match self.width() {
+                        8 => read_volatile(virt as *const u8) as u32,
+                        16 => read_volatile(virt as *const u16) as u32,
+                        _ => read_volatile(virt as *const u32),
+                    }
+                })
+            }
+            _ => Err(AcpiError::Unsupported),
+        }
+    }
+
+    pub fn write(&self, value: u32) -> Result<(), AcpiError> {
+        match self.address_space {
+            ADDRESS_SPACE_IO => {
+                let port = self.address as u16;
+                match self.width() {
+                    8 => outb(port, value as u8),
+                    16 => outw(port, value as u16),
+                    _ => outl(port, value),
+                }
+                Ok(())
+            }
+            ADDRESS_SPACE_MEMORY => {
+                let virt = map_mmio(self.address)?;
+                unsafe {
+                    match self.width() {
+                        8 => write_volatile(virt as *mut u8, value as u8),
+                        16 => write_volatile(virt as *mut u16, value as u16),
+                        _ => write_volatile(virt as *mut u32, value),
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(AcpiError::Unsupported),
+        }
+    }
+}
+
+/// Variable-length `(type, length, ...)` entries shared by the MADT and SRAT.
+pub struct Subtables {
+    bytes: &'static [u8],
+}
+
+impl Subtables {
+    fn new(bytes: &'static [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl Iterator for Subtables {
+    /// Entry type and the whole entry, type and length bytes included.
+    type Item = (u8, &'static [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [kind, len, ..] = *self.bytes else {
+            return None;
+        };
+        let len = len as usize;
+        if len < 2 || len > self.bytes.len() {
+            return None;
+        }
+
+        let entry = &self.bytes[..len];
+        self.bytes = &self.bytes[len..];
+        Some((kind, entry))
+    }
+}
+
+/// Validate the RSDP at `rsdp_phys` and the root table it points to.
+pub fn init(rsdp_phys: u64, hhdm_offset: u64) -> Result<(), AcpiError> {
+    HHDM_OFFSET.store(hhdm_offset, Ordering::Relaxed);
+
+    let rsdp = unsafe { &*(firmware_at(rsdp_phys, size_of::<Rsdp>() as u64) as *const Rsdp) };
+    if rsdp.signature != RSDP_SIGNATURE {
+        return Err(AcpiError::BadRsdp);
+    }
+    if !checksum_ok(unsafe { core::slice::from_raw_parts(rsdp as *const Rsdp as *const u8, RSDP_V1_LEN) }) {
+        return Err(AcpiError::BadChecksum);
+    }
+
+    let (root, signature) = if rsdp.revision >= RSDP_REVISION_XSDT && rsdp.xsdt_address != 0 {
+        let len = rsdp.length as usize;
+        firmware_at(rsdp_phys, len as u64);
+        let bytes = unsafe { core::slice::from_raw_parts(rsdp as *const Rsdp as *const u8, len) };
+        if len < size_of::<Rsdp>() || !checksum_ok(bytes) {
+            return Err(AcpiError::BadChecksum);
+        }
+        (rsdp.xsdt_address, XSDT_SIGNATURE)
+    } else {
+        (rsdp.rsdt_address as u64, RSDT_SIGNATURE)
+    };
+
+    let table = table_at(root);
+    if table.signature != signature {
+        return Err(AcpiError::BadRootTable);
+    }
+    if !table.is_valid() {
+        return Err(AcpiError::BadChecksum);
+    }
+
+    ROOT_TABLE.store(root, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Stop handing out tables so the memory holding them can be reclaimed. Anything
+/// needed from a table other than the FADT must have been read before.
+pub fn release() {
+    fadt::retain();
+    RELEASED.store(true, Ordering::Relaxed);
+    ROOT_TABLE.store(0, Ordering::Relaxed);
+}
+
+/// Print the tables present, and the timers, PCIe configuration space and NUMA topology
+/// they describe.
+pub fn print_summary() {
+    let mut signatures: Vec<[u8; 4]> = tables().map(|table| table.signature).collect();
+    signatures.sort_unstable();
+    print!("acpi tables:");
+    for signature in &signatures {
+        let name = core::str::from_utf8(signature).unwrap_or("????");
+        print!(" {}", name);
+    }
+    println!();
+
+    match fadt::fadt() {
+        Some(fadt) => {
+            let pm_timer = match (fadt.pm_timer, fadt.pm_timer_32bit) {
+                (None, _) => "none",
+                (Some(_), false) => "24-bit",
+                (Some(_), true) => "32-bit",
+            };
+            let reset = if fadt.reset_register.is_some() { "supported" } else { "none" };
+            let poweroff = match fadt.s5_sleep_types {
+                Some(_) if fadt.pm1a_control.is_some() => "supported",
+                _ => "none",
+            };
+            let dsdt = fadt.dsdt;
+            println!("fadt: dsdt {:#x}, pm timer {}, reset {}, poweroff {}", dsdt, pm_timer, reset, poweroff);
+        }
+        None => println!("fadt: none"),
+    }
+
+    match hpet::hpet() {
+        Some(hpet) => {
+            let (base, comparators) = (hpet.base_address.address, hpet.comparators);
+            let bits = if hpet.counter_64bit { 64 } else { 32 };
+            println!("hpet: {:#x}, {} comparators, {}-bit counter", base, comparators, bits);
+        }
+        None => println!("hpet: none"),
+    }
+
+    for region in mcfg::regions() {
+        let (segment, start_bus, end_bus, base) = (region.segment, region.start_bus, region.end_bus, region.base);
+        println!("ecam: segment {} buses {}..={} at {:#x}", segment, start_bus, end_bus, base);
+    }
+
+    for entry in srat::entries() {
+        match entry {
+            SratEntry::ProcessorAffinity { proximity_domain, apic_id, enabled: true } => {
+                println!("numa domain {}: apic {}", proximity_domain, apic_id)
+            }
+            SratEntry::X2ApicAffinity { proximity_domain, x2apic_id, enabled: true } => {
+                println!("numa domain {}: x2apic {}", proximity_domain, x2apic_id)
+            }
+            SratEntry::MemoryAffinity {
+                proximity_domain,
+                base,
+                length,
+                enabled: true,
+                hot_pluggable,
+                non_volatile,
+            } => {
+                let end = base + length;
+                let hot_pluggable = if hot_pluggable { " hot-pluggable" } else { "" };
+                let non_volatile = if non_volatile { " non-volatile" } else { "" };
+                println!(
+                    "numa domain {}: memory {:#x}..{:#x}{}{}",
+                    proximity_domain, base, end, hot_pluggable, non_volatile
+                );
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(slit) = slit::slit() {
+        for from in 0..slit.localities() {
+            print!("numa distances from {}:", from);
+            for distance in slit.row(from) {
+                print!(" {}", distance);
+            }
+            println!();
+        }
+    }
+}
+
+/// Every table listed in the root table whose checksum verifies.
+pub fn tables() -> impl Iterator<Item = &'static SdtHeader> {
+    let root = ROOT_TABLE.load(Ordering::Relaxed);
+    let (entries, entry_size): (&'static [u8], usize) = match root {
+        0 => (&[], 4),
+        root => {
+            let root = table_at(root);
+            let entry_size = if root.signature == XSDT_SIGNATURE { 8 } else { 4 };
+            (root.body(), entry_size)
+        }
+    };
+
+    entries
+        .chunks_exact(entry_size)
+        .map(|entry| match entry.len() {
+            8 => u64_at(entry, 0).unwrap_or(0),
+            _ => u32_at(entry, 0).unwrap_or(0) as u64,
+        })
+        .filter(|&phys_addr| phys_addr != 0)
+        .map(table_at)
+        .filter(|table| table.is_valid())
+}
+
+/// First valid table with `signature`, or None if absent or init() has not run.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    tables().find(|table| table.signature == *signature)
+}
+
+/// Table at `phys_addr` if its checksum verifies, for tables reached through another
+/// table rather than the root (the DSDT).
+fn valid_table_at(phys_addr: u64) -> Option<&'static SdtHeader> {
+    if phys_addr == 0 || RELEASED.load(Ordering::Relaxed) {
+        return None;
+    }
+    Some(table_at(phys_addr)).filter(|table| table.is_valid())
+}
+
+fn table_at(phys_addr: u64) -> &'static SdtHeader {
+    let header = unsafe { &*(firmware_at(phys_addr, size_of::<SdtHeader>() as u64) as *const SdtHeader) };
+    firmware_at(phys_addr, header.length as u64);
+    header
+}
+
+/// Virtual address of `size` bytes of firmware memory at `phys_addr`, which tables in
+/// reserved or NVS memory first need mapped into the direct map.
+fn firmware_at(phys_addr: u64, size: u64) -> u64 {
+    address_space::map_firmware(phys_addr, size, HHDM_OFFSET.load(Ordering::Relaxed));
+    phys_to_virt(phys_addr)
+}
+
+/// Virtual address of the MMIO register at `phys_addr`, mapping its page uncached into
+/// the MMIO window unless an earlier register already shares it.
+fn map_mmio(phys_addr: u64) -> Result<u64, AcpiError> {
+    let phys_page = phys_addr & !(PAGE_SIZE - 1);
+    let offset = phys_addr & (PAGE_SIZE - 1);
+    let mut mappings = MMIO_MAPPINGS.lock();
+
+    if let Some(slot) = mappings.iter().position(|mapping| *mapping == Some(phys_page)) {
+        return Ok(MMIO_VIRT + slot as u64 * PAGE_SIZE + offset);
+    }
+
+    let slot = mappings.iter().position(Option::is_none).ok_or(AcpiError::MmioWindowFull)?;
+    let virt = MMIO_VIRT + slot as u64 * PAGE_SIZE;
+    let flags = PageFlags::WRITABLE | PageFlags::CACHE_DISABLE;
+    page::map(virt, phys_page, flags, HHDM_OFFSET.load(Ordering::Relaxed)).map_err(AcpiError::Map)?;
+    mappings[slot] = Some(phys_page);
+
+    Ok(virt + offset)
+}
+
+fn phys_to_virt(phys_addr: u64) -> u64 {
+    phys_addr + HHDM_OFFSET.load(Ordering::Relaxed)
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
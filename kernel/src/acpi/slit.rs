@@ -0,0 +1,47 @@
+//! System Locality Information Table: relative memory latency between NUMA
+//! proximity domains.
+
+use super::u64_at;
+
+pub const SIGNATURE: [u8; 4] = *b"SLIT";
+
+const OFFSET_LOCALITIES: usize = 36;
+const OFFSET_MATRIX: usize = 44;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Slit {
+    localities: u64,
+    matrix: &'static [u8],
+}
+
+impl Slit {
+    pub fn localities(&self) -> u64 {
+        self.localities
+    }
+
+    /// Relative distance from locality `from` to `to`, or None if either is out of range.
+    /// A locality is 10 from itself; 255 means unreachable.
+    pub fn distance(&self, from: u64, to: u64) -> Option<u8> {
+        if from >= self.localities || to >= self.localities {
+            return None;
+        }
+        self.matrix.get((from * self.localities + to) as usize).copied()
+    }
+
+    /// Distances from locality `from` to every locality, in locality order.
+    pub fn row(&self, from: u64) -> impl Iterator<Item = u8> + '_ {
+        (0..self.localities).filter_map(move |to| self.distance(from, to))
+    }
+}
+
+/// The SLIT, or None if ACPI has none or its matrix is truncated.
+pub fn slit() -> Option<Slit> {
+    let bytes = super::find_table(&SIGNATURE)?.bytes();
+    let localities = u64_at(bytes, OFFSET_LOCALITIES)?;
+    let matrix = bytes.get(OFFSET_MATRIX..)?;
+    if (matrix.len() as u64) < localities.checked_mul(localities)? {
+        return None;
+    }
+
+    Some(Slit { localities, matrix })
+}
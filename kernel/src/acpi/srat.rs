@@ -0,0 +1,99 @@
+//! System Resource Affinity Table: which NUMA proximity domain each CPU and memory
+//! range belongs to.
+
+#![allow(dead_code, reason = "Other keeps the type of entries this parser skips, for diagnostics")]
+
+use super::{Subtables, u32_at, u64_at};
+
+pub const SIGNATURE: [u8; 4] = *b"SRAT";
+
+/// Header and 12 reserved bytes precede the entries.
+const ENTRIES_OFFSET: usize = 48;
+
+const ENTRY_PROCESSOR_AFFINITY: u8 = 0;
+const ENTRY_MEMORY_AFFINITY: u8 = 1;
+const ENTRY_X2APIC_AFFINITY: u8 = 2;
+
+const FLAG_ENABLED: u32 = 1 << 0;
+const MEMORY_FLAG_HOT_PLUGGABLE: u32 = 1 << 1;
+const MEMORY_FLAG_NON_VOLATILE: u32 = 1 << 2;
+
+#[derive(Clone, Copy, Debug)]
+pub enum SratEntry {
+    ProcessorAffinity {
+        proximity_domain: u32,
+        apic_id: u8,
+        enabled: bool,
+    },
+    MemoryAffinity {
+        proximity_domain: u32,
+        base: u64,
+        length: u64,
+        enabled: bool,
+        hot_pluggable: bool,
+        non_volatile: bool,
+    },
+    X2ApicAffinity {
+        proximity_domain: u32,
+        x2apic_id: u32,
+        enabled: bool,
+    },
+    /// Entry type this parser does not decode.
+    Other(u8),
+}
+
+pub struct Entries {
+    subtables: Subtables,
+}
+
+impl Iterator for Entries {
+    type Item = SratEntry;
+
+    fn next(&mut self) -> Option<SratEntry> {
+        let (kind, entry) = self.subtables.next()?;
+        Some(parse_entry(kind, entry).unwrap_or(SratEntry::Other(kind)))
+    }
+}
+
+/// None if the entry is too short for its type.
+fn parse_entry(kind: u8, entry: &[u8]) -> Option<SratEntry> {
+    Some(match kind {
+        ENTRY_PROCESSOR_AFFINITY => {
+            // Low byte at 2, high three bytes at 9..12.
+            let high = u32_at(entry, 8)? >> 8;
+            SratEntry::ProcessorAffinity {
+                proximity_domain: (high << 8) | *entry.get(2)? as u32,
+                apic_id: *entry.get(3)?,
+                enabled: u32_at(entry, 4)? & FLAG_ENABLED != 0,
+            }
+        }
+        ENTRY_MEMORY_AFFINITY => {
+            let flags = u32_at(entry, 28)?;
+            SratEntry::MemoryAffinity {
+                proximity_domain: u32_at(entry, 2)?,
+                base: u64_at(entry, 8)?,
+                length: u64_at(entry, 16)?,
+                enabled: flags & FLAG_ENABLED != 0,
+                hot_pluggable: flags & MEMORY_FLAG_HOT_PLUGGABLE != 0,
+                non_volatile: flags & MEMORY_FLAG_NON_VOLATILE != 0,
+            }
+        }
+        ENTRY_X2APIC_AFFINITY => SratEntry::X2ApicAffinity {
+            proximity_domain: u32_at(entry, 4)?,
+            x2apic_id: u32_at(entry, 8)?,
+            enabled: u32_at(entry, 12)? & FLAG_ENABLED != 0,
+        },
+        kind => SratEntry::Other(kind),
+    })
+}
+
+/// Entries of the SRAT, empty if ACPI has no SRAT (a single-domain machine).
+pub fn entries() -> Entries {
+    let bytes = match super::find_table(&SIGNATURE) {
+        Some(table) => table.bytes().get(ENTRIES_OFFSET..).unwrap_or(&[]),
+        None => &[],
+    };
+    Entries {
+        subtables: Subtables::new(bytes),
+    }
+}
@@ -0,0 +1,206 @@
+//! Local APIC: the per-CPU interrupt controller, its timer and inter-processor
+//! interrupts.
+//!
+//! x2APIC mode is used when the CPU supports it, with registers accessed through MSRs.
+//! Otherwise the xAPIC register page is mapped uncached at LAPIC_VIRT and accessed with
+//! volatile loads and stores. Registers are named by their xAPIC page offset in both
+//! modes.
+
+use core::arch::x86_64::__cpuid;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::mm::page::{self, MapError, PageFlags};
+
+use super::msr::{self, IA32_APIC_BASE, X2APIC_BASE};
+use super::{pic, pit};
+
+pub const TIMER_VECTOR: u8 = 0x30;
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// Virtual address of the xAPIC register page.
+const LAPIC_VIRT: u64 = 0xffffffffa0000000;
+
+const CPUID_X2APIC: u32 = 1 << 21;
+
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+const REG_ID: u32 = 0x20;
+const REG_TPR: u32 = 0x80;
+const REG_EOI: u32 = 0xb0;
+const REG_SVR: u32 = 0xf0;
+const REG_ICR_LOW: u32 = 0x300;
+const REG_ICR_HIGH: u32 = 0x310;
+const REG_LVT_TIMER: u32 = 0x320;
+const REG_TIMER_INITIAL: u32 = 0x380;
+const REG_TIMER_CURRENT: u32 = 0x390;
+const REG_TIMER_DIVIDE: u32 = 0x3e0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+const ICR_FIXED: u32 = 0b000 << 8;
+const ICR_NMI: u32 = 0b100 << 8;
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const XAPIC_DESTINATION_SHIFT: u32 = 24;
+
+const CALIBRATION_US: u64 = 10_000;
+const CALIBRATION_ROUNDS: usize = 3;
+
+static IS_X2APIC: AtomicBool = AtomicBool::new(false);
+static TIMER_HZ: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerMode {
+    OneShot,
+    Periodic,
+}
+
+/// Pick xAPIC or x2APIC mode, map the xAPIC registers if needed, disable the legacy
+/// PICs, enable the bootstrap processor's local APIC and calibrate its timer against
+/// the PIT. Must run on the bootstrap processor before init_ap() runs anywhere.
+pub fn init(hhdm_offset: u64) -> Result<(), MapError> {
+    let x2apic = __cpuid(1).ecx & CPUID_X2APIC != 0;
+    IS_X2APIC.store(x2apic, Ordering::Relaxed);
+
+    if !x2apic {
+        let phys_addr = msr::rdmsr(IA32_APIC_BASE) & APIC_BASE_ADDR_MASK;
+        let flags = PageFlags::WRITABLE | PageFlags::CACHE_DISABLE;
+        page::map(LAPIC_VIRT, phys_addr, flags, hhdm_offset)?;
+    }
+
+    pic::disable();
+    enable_local();
+    TIMER_HZ.store(calibrate(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Enable the calling application processor's local APIC in the mode init() chose.
+pub fn init_ap() {
+    enable_local();
+}
+
+pub fn is_x2apic() -> bool {
+    IS_X2APIC.load(Ordering::Relaxed)
+}
+
+/// Local APIC ID of the calling CPU.
+pub fn id() -> u32 {
+    let id = read(REG_ID);
+    if is_x2apic() { id } else { id >> XAPIC_DESTINATION_SHIFT }
+}
+
+/// Calibrated timer frequency after the divide-by-16, 0 before init().
+pub fn timer_frequency_hz() -> u64 {
+    TIMER_HZ.load(Ordering::Relaxed)
+}
+
+/// Signal end of interrupt for the interrupt being handled.
+pub fn eoi() {
+    write(REG_EOI, 0);
+}
+
+/// Fire `vector` on the calling CPU after `micros` microseconds, once or every period.
+pub fn start_timer(mode: TimerMode, vector: u8, micros: u64) {
+    let count = (timer_frequency_hz() * micros / 1_000_000).clamp(1, u32::MAX as u64) as u32;
+    let mode_bits = match mode {
+        TimerMode::OneShot => 0,
+        TimerMode::Periodic => LVT_TIMER_PERIODIC,
+    };
+
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(REG_LVT_TIMER, mode_bits | vector as u32);
+    write(REG_TIMER_INITIAL, count);
+}
+
+pub fn stop_timer() {
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_TIMER_INITIAL, 0);
+}
+
+/// Send `vector` to the CPU with local APIC ID `destination`.
+pub fn send_fixed(destination: u32, vector: u8) {
+    send_ipi(destination, ICR_FIXED | vector as u32);
+}
+
+pub fn send_nmi(destination: u32) {
+    send_ipi(destination, ICR_NMI);
+}
+
+/// Put the destination CPU into wait-for-SIPI state.
+pub fn send_init(destination: u32) {
+    send_ipi(destination, ICR_INIT | ICR_LEVEL_ASSERT);
+}
+
+/// Start the destination CPU in real mode at physical address `page * 4096`.
+pub fn send_startup(destination: u32, page: u8) {
+    send_ipi(destination, ICR_STARTUP | ICR_LEVEL_ASSERT | page as u32);
+}
+
+fn send_ipi(destination: u32, command: u32) {
+    if is_x2apic() {
+        let icr = ((destination as u64) << 32) | command as u64;
+        msr::wrmsr(X2APIC_BASE + REG_ICR_LOW / 16, icr);
+        return;
+    }
+
+    write(REG_ICR_HIGH, destination << XAPIC_DESTINATION_SHIFT);
+    write(REG_ICR_LOW, command);
+    while read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+fn enable_local() {
+    let mut base = msr::rdmsr(IA32_APIC_BASE) | APIC_BASE_ENABLE;
+    if is_x2apic() {
+        base |= APIC_BASE_X2APIC;
+    }
+    msr::wrmsr(IA32_APIC_BASE, base);
+
+    write(REG_TPR, 0);
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+/// Shortest of several PIT-timed countdowns, as for the TSC.
+fn calibrate() -> u64 {
+    let ticks = pit::ticks_for_us(CALIBRATION_US);
+    let mut best = u64::MAX;
+
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    for _ in 0..CALIBRATION_ROUNDS {
+        pit::start_oneshot(ticks);
+        write(REG_TIMER_INITIAL, u32::MAX);
+        while !pit::expired() {
+            core::hint::spin_loop();
+        }
+        let elapsed = u32::MAX - read(REG_TIMER_CURRENT);
+        best = best.min(elapsed as u64);
+    }
+    write(REG_TIMER_INITIAL, 0);
+
+    best * 1_000_000 / CALIBRATION_US
+}
+
+fn read(reg: u32) -> u32 {
+    if is_x2apic() {
+        return msr::rdmsr(X2APIC_BASE + reg / 16) as u32;
+    }
+    unsafe { read_volatile((LAPIC_VIRT + reg as u64) as *const u32) }
+}
+
+fn write(reg: u32, value: u32) {
+    if is_x2apic() {
+        msr::wrmsr(X2APIC_BASE + reg / 16, value as u64);
+        return;
+    }
+    unsafe { write_volatile((LAPIC_VIRT + reg as u64) as *mut u32, value) };
+}
@@ -1,16 +1,49 @@
 use core::arch::asm;
 use core::arch::x86_64::__cpuid;
-use core::ptr::addr_of;
+use core::mem::{offset_of, size_of};
+use core::ptr::{addr_of, null};
+use core::sync::atomic::{AtomicU8, AtomicU16, Ordering};
+
+use crate::causality::buffer::{self, EventRingBuffer};
+use crate::causality::context::ContextStack;
 
 use super::gdt::{Gdt, TSS_SELECTOR};
+use super::msr::{self, IA32_EFER, IA32_GS_BASE, IA32_KERNEL_GS_BASE};
 use super::tss::Tss;
 
+/// Upper bound on started CPUs; logical indices are dense in `0..MAX_CPUS`.
+pub const MAX_CPUS: usize = 16;
+
 const APIC_ID_SHIFT: u32 = 24;
 const APIC_ID_MASK: u32 = 0xFF;
+const CPUID_EXTENDED_TOPOLOGY: u32 = 0xb;
+const CPUID_EXTENDED_MAX_LEAF: u32 = 0x8000_0000;
+const CPUID_EXTENDED_FEATURES: u32 = 0x8000_0001;
+const CPUID_NX: u32 = 1 << 20;
+const CPUID_PDPE1GB: u32 = 1 << 26;
+/// No-execute enable: makes bit 63 of page table entries the NX bit instead of reserved.
+const EFER_NXE: u64 = 1 << 11;
 
-static mut CPU: Cpu = Cpu::new();
+static mut CPUS: [Cpu; MAX_CPUS] = [const { Cpu::new() }; MAX_CPUS];
+static NEXT_INDEX: AtomicU16 = AtomicU16::new(0);
+/// CPUID pdpe1gb bit, plus one; 0 until first queried.
+static PDPE1GB: AtomicU8 = AtomicU8::new(0);
 
+/// Per-CPU data block, reached through the GS base of the CPU that owns it.
+///
+/// The kernel has no user mode, so GS always holds the kernel base and `swapgs` is
+/// never needed; `IA32_KERNEL_GS_BASE` is set to the same block so a stray `swapgs`
+/// is harmless.
+#[repr(C)]
 pub struct Cpu {
+    /// Address of this block. Must stay first: `gs:[0]` yields the block itself.
+    self_ptr: *const Cpu,
+    /// Dense logical index, in start order. Used as the causality core id.
+    index: u16,
+    apic_id: u32,
+    /// Causal context of the code running on this CPU.
+    context: ContextStack,
+    events: *const EventRingBuffer,
     gdt: Gdt,
     tss: Tss,
     df_stack: [u8; 4096],
@@ -19,18 +52,54 @@ pub struct Cpu {
 impl Cpu {
     const fn new() -> Self {
         Self {
+            self_ptr: null(),
+            index: 0,
+            apic_id: 0,
+            context: ContextStack::new(),
+            events: null(),
             gdt: Gdt::new(),
             tss: Tss::new(),
             df_stack: [0; 4096],
         }
     }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id
+    }
+
+    pub fn context(&self) -> &ContextStack {
+        &self.context
+    }
+
+    /// This CPU's causality ring buffer.
+    pub fn events(&self) -> &'static EventRingBuffer {
+        unsafe { &*self.events }
+    }
 }
 
+/// Claim the next logical index for the calling CPU, load its GDT and TSS and point
+/// its GS base at its per-CPU block. Must run once on every CPU, after causality::init()
+/// and before anything that uses current().
 pub fn init() {
+    let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+    if index as usize >= MAX_CPUS {
+        panic!("More than {} cpus started", MAX_CPUS);
+    }
+
     unsafe {
-        let cpu = &mut *(&raw mut CPU);
+        let cpus = &raw mut CPUS;
+        let cpu = &mut (*cpus)[index as usize];
         let df_stack_top = (addr_of!(cpu.df_stack)) as u64 + (cpu.df_stack.len()) as u64;
 
+        cpu.self_ptr = cpu as *const Cpu;
+        cpu.index = index;
+        cpu.apic_id = read_apic_id();
+        cpu.events = buffer::for_core(index);
+
         cpu.tss.init(df_stack_top);
 
         let tss_addr = addr_of!(cpu.tss) as u64;
@@ -38,6 +107,9 @@ pub fn init() {
 
         cpu.gdt.load();
         Tss::load(TSS_SELECTOR);
+
+        msr::wrmsr(IA32_GS_BASE, cpu.self_ptr as u64);
+        msr::wrmsr(IA32_KERNEL_GS_BASE, cpu.self_ptr as u64);
     }
 }
 
@@ -54,9 +126,97 @@ pub fn switch_stack(stack_top: u64, target: extern "C" fn() -> !) -> ! {
     }
 }
 
-/// Uses CPUID leaf 1 initial APIC ID (bootstrap strategy for now, will adjust to more
-/// sophisticated method as needed).
+pub fn enable_interrupts() {
+    unsafe { asm!("sti", options(nomem, nostack)) };
+}
+
+/// Disable interrupts and halt the calling CPU for good.
+pub fn halt() -> ! {
+    loop {
+        unsafe { asm!("cli", "hlt", options(nomem, nostack)) };
+    }
+}
+
+/// Whether init() has run on the calling CPU, so current() may be used. Reads the GS
+/// base MSR rather than the per-CPU block, so it is safe on a CPU that faulted before
+/// init().
+pub fn is_initialized() -> bool {
+    let base = msr::rdmsr(IA32_GS_BASE);
+    let start = (&raw const CPUS) as u64;
+    (start..start + size_of::<[Cpu; MAX_CPUS]>() as u64).contains(&base)
+}
+
+/// Local APIC IDs of the CPUs that have run init(). A CPU that has only just claimed
+/// its index may still show as 0.
+pub fn started_apic_ids() -> impl Iterator<Item = u32> {
+    let started = (NEXT_INDEX.load(Ordering::Acquire) as usize).min(MAX_CPUS);
+    (0..started).map(|index| unsafe { CPUS[index].apic_id })
+}
+
+/// The calling CPU's per-CPU block. init() must have run on this CPU.
+#[inline]
+pub fn current() -> &'static Cpu {
+    let cpu: *const Cpu;
+    unsafe {
+        asm!(
+            "mov {}, gs:[0]",
+            out(reg) cpu,
+            options(nostack, preserves_flags, readonly)
+        );
+        &*cpu
+    }
+}
+
+/// Logical index of the calling CPU, read straight from its per-CPU block.
+#[inline]
 pub fn current_core_id() -> u16 {
-    let cpu_id = unsafe { __cpuid(1) };
-    ((cpu_id.ebx >> APIC_ID_SHIFT) & APIC_ID_MASK) as u16
+    let index: u16;
+    unsafe {
+        asm!(
+            "mov {:x}, gs:[{}]",
+            out(reg) index,
+            const offset_of!(Cpu, index),
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    index
+}
+
+/// Whether the CPU supports 1 GiB pages. Cached, as CPUID may trap to a hypervisor.
+pub fn has_1gib_pages() -> bool {
+    let cached = PDPE1GB.load(Ordering::Relaxed);
+    if cached != 0 {
+        return cached == 2;
+    }
+
+    let supported = __cpuid(CPUID_EXTENDED_MAX_LEAF).eax >= CPUID_EXTENDED_FEATURES
+        && __cpuid(CPUID_EXTENDED_FEATURES).edx & CPUID_PDPE1GB != 0;
+    PDPE1GB.store(supported as u8 + 1, Ordering::Relaxed);
+    supported
+}
+
+/// Whether the CPU supports no-execute pages.
+pub fn has_no_execute() -> bool {
+    __cpuid(CPUID_EXTENDED_MAX_LEAF).eax >= CPUID_EXTENDED_FEATURES
+        && __cpuid(CPUID_EXTENDED_FEATURES).edx & CPUID_NX != 0
+}
+
+/// Set EFER.NXE on the calling CPU when supported, so `PageFlags::NO_EXECUTE` mappings
+/// are accepted. Without it the bit is reserved and setting it faults.
+pub fn enable_no_execute() {
+    if has_no_execute() {
+        msr::wrmsr(IA32_EFER, msr::rdmsr(IA32_EFER) | EFER_NXE);
+    }
+}
+
+/// x2APIC ID from the extended topology leaf when available (IDs may exceed 255),
+/// otherwise the initial APIC ID from leaf 1.
+fn read_apic_id() -> u32 {
+    let max_leaf = __cpuid(0).eax;
+    if max_leaf >= CPUID_EXTENDED_TOPOLOGY {
+        return __cpuid(CPUID_EXTENDED_TOPOLOGY).edx;
+    }
+
+    let cpu_id = __cpuid(1);
+    (cpu_id.ebx >> APIC_ID_SHIFT) & APIC_ID_MASK
 }
@@ -98,4 +98,12 @@ pub fn init() {
     }
 }
 
+/// Load the already initialized IDT on an application processor.
+pub fn load() {
+    unsafe {
+        let idt = &raw const IDT;
+        (*idt).load();
+    }
+}
+
 
@@ -1,11 +1,22 @@
 use core::arch::naked_asm;
+use super::apic;
+use super::cpu;
 use super::idt::Idt;
+use super::irq;
 use super::mmu::read_cr2;
+use super::pic;
+use super::smp;
+use crate::causality;
+use crate::causality::types::{Cause, EventData, EventId, EventKind, RootCause};
 
 const DIVIDE_BY_ZERO_VEC: usize = 0;
+const NMI_VEC: usize = 2;
 const DOUBLE_FAULT_VEC: usize = 8;
 const GENERAL_PROTECTION_FAULT_VEC: usize = 13;
 const PAGE_FAULT_VEC: usize = 14;
+/// Spurious IRQ 7 and 15 of the masked legacy PICs.
+const PIC_MASTER_SPURIOUS_VEC: usize = pic::VECTOR_OFFSET as usize + 7;
+const PIC_SLAVE_SPURIOUS_VEC: usize = pic::VECTOR_OFFSET as usize + 15;
 
 const NUM_GP_REGS: usize = 15;
 const SAVED_REGS_SIZE: usize = NUM_GP_REGS * 8;
@@ -30,17 +41,22 @@ pub struct InterruptStackFrame {
 
 macro_rules! exception_stub {
     ($name:ident, $handler:ident, no_error_code) => {
-        exception_stub!(@impl $name, $handler, "push 0",);
+        exception_stub!(@impl $name, [], $handler, "push 0",);
     };
 
 
     ($name:ident, $handler:ident, has_error_code) => {
-        exception_stub!(@impl $name, $handler,);
+        exception_stub!(@impl $name, [], $handler,);
     };
 
-    (@impl $name:ident, $handler:ident, $($preamble:tt)*) => {
+    // One stub per vector, instantiated with the vector as a const parameter.
+    ($name:ident<const $vector:ident: u8>, $handler:path, no_error_code) => {
+        exception_stub!(@impl $name, [<const $vector: u8>], $handler, "push 0",);
+    };
+
+    (@impl $name:ident, [$($generics:tt)*], $handler:path, $($preamble:tt)*) => {
         #[unsafe(naked)]
-        pub unsafe extern "C" fn $name() {
+        pub unsafe extern "C" fn $name $($generics)* () {
             naked_asm!(
                 $($preamble)*
                 "push rax",
@@ -85,24 +101,90 @@ macro_rules! exception_stub {
 }
 
 exception_stub!(divide_by_zero_stub, divide_by_zero_handler, no_error_code);
+exception_stub!(nmi_stub, nmi_handler, no_error_code);
 exception_stub!(double_fault_stub, double_fault_handler, has_error_code);
 exception_stub!(general_protection_fault_stub, general_protection_fault_handler, has_error_code);
 exception_stub!(page_fault_stub, page_fault_handler, has_error_code);
+exception_stub!(apic_timer_stub, apic_timer_handler, no_error_code);
+exception_stub!(spurious_stub, spurious_handler, no_error_code);
+exception_stub!(irq_stub<const VECTOR: u8>, irq_handler::<VECTOR>, no_error_code);
+
+/// Stubs for the vectors irq::allocate() hands out, starting at irq::FIRST_VECTOR.
+const IRQ_STUBS: [unsafe extern "C" fn(); irq::VECTOR_COUNT] = [
+    irq_stub::<0x40>, irq_stub::<0x41>, irq_stub::<0x42>, irq_stub::<0x43>,
+    irq_stub::<0x44>, irq_stub::<0x45>, irq_stub::<0x46>, irq_stub::<0x47>,
+    irq_stub::<0x48>, irq_stub::<0x49>, irq_stub::<0x4a>, irq_stub::<0x4b>,
+    irq_stub::<0x4c>, irq_stub::<0x4d>, irq_stub::<0x4e>, irq_stub::<0x4f>,
+    irq_stub::<0x50>, irq_stub::<0x51>, irq_stub::<0x52>, irq_stub::<0x53>,
+    irq_stub::<0x54>, irq_stub::<0x55>, irq_stub::<0x56>, irq_stub::<0x57>,
+    irq_stub::<0x58>, irq_stub::<0x59>, irq_stub::<0x5a>, irq_stub::<0x5b>,
+    irq_stub::<0x5c>, irq_stub::<0x5d>, irq_stub::<0x5e>, irq_stub::<0x5f>,
+];
+const _: () = assert!(irq::FIRST_VECTOR == 0x40, "IRQ_STUBS are instantiated for vectors 0x40..0x60");
 
 pub fn register_handlers(idt: &mut Idt) {
     idt.set_handler(DIVIDE_BY_ZERO_VEC, divide_by_zero_stub);
+    idt.set_handler(NMI_VEC, nmi_stub);
     idt.set_handler(DOUBLE_FAULT_VEC, double_fault_stub);
     idt.set_handler(GENERAL_PROTECTION_FAULT_VEC, general_protection_fault_stub);
     idt.set_handler(PAGE_FAULT_VEC, page_fault_stub);
+    idt.set_handler(apic::TIMER_VECTOR as usize, apic_timer_stub);
+    idt.set_handler(apic::SPURIOUS_VECTOR as usize, spurious_stub);
+    idt.set_handler(PIC_MASTER_SPURIOUS_VEC, spurious_stub);
+    idt.set_handler(PIC_SLAVE_SPURIOUS_VEC, spurious_stub);
+    for (idx, stub) in IRQ_STUBS.iter().enumerate() {
+        idt.set_handler(irq::FIRST_VECTOR as usize + idx, *stub);
+    }
 
     idt.set_ist(DOUBLE_FAULT_VEC, 1);
 }
 
+/// Record an exception as the root of a new causal chain. Exceptions taken before the
+/// causality module or the CPU's per-CPU block is up are not recorded.
+fn record_exception(vector: usize, frame: &InterruptStackFrame, cr2: u64) -> Option<EventId> {
+    if !causality::is_initialized() || !cpu::is_initialized() {
+        return None;
+    }
+
+    let data = EventData::Exception {
+        vector: vector as u8,
+        error_code: frame.err_code,
+        rip: frame.rip,
+        cr2,
+    };
+    Some(causality::record(EventKind::Exception, Cause::Root(RootCause::Hardware), data))
+}
+
+/// Record entry into an external interrupt handler. Work done by the handler should be
+/// caused by the returned event.
+pub fn record_irq_entry(vector: u8, frame: &InterruptStackFrame) -> EventId {
+    let data = EventData::Irq { vector, rip: frame.rip };
+    causality::record(EventKind::IrqEntry, Cause::Root(RootCause::Hardware), data)
+}
+
+/// Record the end of the interrupt handler that started with `entry`.
+pub fn record_irq_exit(vector: u8, entry: EventId, frame: &InterruptStackFrame) -> EventId {
+    let data = EventData::Irq { vector, rip: frame.rip };
+    causality::record(EventKind::IrqExit, Cause::CausedBy(entry), data)
+}
+
+/// The kernel sends NMIs only to stop the other CPUs while one reports a panic; halt
+/// when that is under way. Any other NMI comes from the hardware (a watchdog, a memory
+/// or bus error) and is recorded before returning.
+extern "C" fn nmi_handler(frame: &InterruptStackFrame) {
+    if smp::stopped_by_other() {
+        cpu::halt();
+    }
+    record_exception(NMI_VEC, frame, 0);
+}
+
 extern "C" fn divide_by_zero_handler(frame: &InterruptStackFrame) {
+    record_exception(DIVIDE_BY_ZERO_VEC, frame, 0);
     panic!("Divide by zero at {:#x}", frame.rip);
 }
 
 extern "C" fn double_fault_handler(frame: &InterruptStackFrame) {
+    record_exception(DOUBLE_FAULT_VEC, frame, 0);
     panic!("Double fault at {:#x}
         RSP: {:#x}"
         , frame.rip, frame.rsp
@@ -111,6 +193,7 @@ extern "C" fn double_fault_handler(frame: &InterruptStackFrame) {
 
 extern "C" fn page_fault_handler(frame: &InterruptStackFrame) {
     let fault_addr = read_cr2();
+    record_exception(PAGE_FAULT_VEC, frame, fault_addr);
     let rip = frame.rip;
     let err_code = frame.err_code;
     let rsp = frame.rsp;
@@ -126,6 +209,7 @@ extern "C" fn page_fault_handler(frame: &InterruptStackFrame) {
 }
 
 extern "C" fn general_protection_fault_handler(frame: &InterruptStackFrame) {
+    record_exception(GENERAL_PROTECTION_FAULT_VEC, frame, 0);
     let rip = frame.rip;
     let err_code = frame.err_code;
     let rsp = frame.rsp;
@@ -137,3 +221,16 @@ extern "C" fn general_protection_fault_handler(frame: &InterruptStackFrame) {
         RSP: {rsp:#x}"
     );
 }
+
+extern "C" fn apic_timer_handler(frame: &InterruptStackFrame) {
+    let entry = record_irq_entry(apic::TIMER_VECTOR, frame);
+    apic::eoi();
+    record_irq_exit(apic::TIMER_VECTOR, entry, frame);
+}
+
+extern "C" fn irq_handler<const VECTOR: u8>(frame: &InterruptStackFrame) {
+    irq::dispatch(VECTOR, frame);
+}
+
+/// Spurious interrupts must not be acknowledged.
+extern "C" fn spurious_handler(_frame: &InterruptStackFrame) {}
@@ -0,0 +1,212 @@
+//! I/O APICs: route global system interrupts (GSIs) from devices to local APICs.
+//!
+//! Controllers and legacy ISA IRQ overrides come from the ACPI MADT. Each controller's
+//! register window is mapped uncached at IOAPIC_VIRT + n * 4096. Routing is done from
+//! one CPU at a time; the select/window register pair is not locked.
+
+use core::ptr::{read_volatile, write_volatile};
+use core::slice;
+
+use crate::acpi::madt::{self, MadtEntry};
+use crate::mm::page::{self, MapError, PageFlags};
+
+use super::irq;
+
+const MAX_IOAPICS: usize = 8;
+const LEGACY_IRQS: usize = 16;
+
+/// Virtual address of the first controller's registers, right after the local APIC page.
+const IOAPIC_VIRT: u64 = 0xffffffffa0001000;
+const PAGE_SIZE: u64 = 4096;
+const REGISTER_OFFSET_MASK: u64 = PAGE_SIZE - 1;
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION: u32 = 0x10;
+const VERSION_MAX_ENTRY_SHIFT: u32 = 16;
+const VERSION_MAX_ENTRY_MASK: u32 = 0xff;
+
+const REDIRECT_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECT_LEVEL: u64 = 1 << 15;
+const REDIRECT_MASKED: u64 = 1 << 16;
+const REDIRECT_DESTINATION_SHIFT: u64 = 56;
+/// Physical destination mode addresses APIC IDs up to 255.
+const MAX_DESTINATION: u32 = 0xff;
+
+static mut IOAPICS: [IoApic; MAX_IOAPICS] = [IoApic::empty(); MAX_IOAPICS];
+static mut IOAPIC_COUNT: usize = 0;
+static mut LEGACY_GSIS: [Gsi; LEGACY_IRQS] = legacy_identity_map();
+
+/// A global system interrupt and how its line is signalled.
+#[derive(Clone, Copy, Debug)]
+pub struct Gsi {
+    pub number: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+impl Gsi {
+    /// ISA default signalling: active high, edge triggered.
+    pub const fn isa(number: u32) -> Self {
+        Self {
+            number,
+            active_low: false,
+            level_triggered: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum IoApicError {
+    Map(MapError),
+    TooManyIoApics,
+    NoIoApic(u32),
+    NoFreeVector,
+    DestinationOutOfRange(u32),
+}
+
+#[derive(Clone, Copy)]
+struct IoApic {
+    virt: u64,
+    gsi_base: u32,
+    gsi_count: u32,
+}
+
+impl IoApic {
+    const fn empty() -> Self {
+        Self {
+            virt: 0,
+            gsi_base: 0,
+            gsi_count: 0,
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi - self.gsi_base < self.gsi_count
+    }
+
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            write_volatile((self.virt + IOREGSEL) as *mut u32, reg);
+            read_volatile((self.virt + IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            write_volatile((self.virt + IOREGSEL) as *mut u32, reg);
+            write_volatile((self.virt + IOWIN) as *mut u32, value);
+        }
+    }
+
+    fn write_redirection(&self, gsi: u32, entry: u64) {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        // Mask first so the line never fires with a half-written entry.
+        self.write(reg, REDIRECT_MASKED as u32);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+}
+
+/// Map every I/O APIC in the MADT, mask all of their inputs and record legacy IRQ
+/// overrides. Returns how many controllers were found.
+pub fn init(hhdm_offset: u64) -> Result<usize, IoApicError> {
+    let mut count = 0;
+
+    for entry in madt::entries() {
+        match entry {
+            MadtEntry::IoApic { address, gsi_base, .. } => {
+                if count == MAX_IOAPICS {
+                    return Err(IoApicError::TooManyIoApics);
+                }
+
+                let phys_addr = address as u64;
+                let virt = IOAPIC_VIRT + count as u64 * PAGE_SIZE;
+                let flags = PageFlags::WRITABLE | PageFlags::CACHE_DISABLE;
+                page::map(virt, phys_addr & !REGISTER_OFFSET_MASK, flags, hhdm_offset).map_err(IoApicError::Map)?;
+
+                let mut ioapic = IoApic {
+                    virt: virt + (phys_addr & REGISTER_OFFSET_MASK),
+                    gsi_base,
+                    gsi_count: 0,
+                };
+                let max_entry = (ioapic.read(REG_VERSION) >> VERSION_MAX_ENTRY_SHIFT) & VERSION_MAX_ENTRY_MASK;
+                ioapic.gsi_count = max_entry + 1;
+                for gsi in gsi_base..gsi_base + ioapic.gsi_count {
+                    ioapic.write_redirection(gsi, REDIRECT_MASKED);
+                }
+
+                unsafe { IOAPICS[count] = ioapic };
+                count += 1;
+            }
+            MadtEntry::InterruptSourceOverride { source, gsi, active_low, level_triggered }
+                if (source as usize) < LEGACY_IRQS =>
+            {
+                let legacy_gsi = Gsi {
+                    number: gsi,
+                    active_low,
+                    level_triggered,
+                };
+                unsafe { LEGACY_GSIS[source as usize] = legacy_gsi };
+            }
+            _ => {}
+        }
+    }
+
+    unsafe { IOAPIC_COUNT = count };
+    Ok(count)
+}
+
+/// The GSI legacy ISA `irq` is wired to, after MADT overrides.
+pub fn legacy_irq(irq: u8) -> Gsi {
+    let legacy = unsafe { LEGACY_GSIS };
+    legacy.get(irq as usize).copied().unwrap_or(Gsi::isa(irq as u32))
+}
+
+/// Allocate a vector for `handler` and deliver `gsi` to the local APIC with ID
+/// `destination` on it. Returns the vector.
+pub fn route(gsi: Gsi, destination: u32, handler: irq::Handler) -> Result<u8, IoApicError> {
+    if destination > MAX_DESTINATION {
+        return Err(IoApicError::DestinationOutOfRange(destination));
+    }
+    let ioapic = ioapic_for(gsi.number)?;
+    let vector = irq::allocate(handler).ok_or(IoApicError::NoFreeVector)?;
+
+    let mut entry = vector as u64 | ((destination as u64) << REDIRECT_DESTINATION_SHIFT);
+    if gsi.active_low {
+        entry |= REDIRECT_ACTIVE_LOW;
+    }
+    if gsi.level_triggered {
+        entry |= REDIRECT_LEVEL;
+    }
+    ioapic.write_redirection(gsi.number, entry);
+
+    Ok(vector)
+}
+
+/// Stop delivering `gsi`.
+pub fn mask(gsi: u32) -> Result<(), IoApicError> {
+    ioapic_for(gsi)?.write_redirection(gsi, REDIRECT_MASKED);
+    Ok(())
+}
+
+fn ioapic_for(gsi: u32) -> Result<&'static IoApic, IoApicError> {
+    let count = unsafe { IOAPIC_COUNT };
+    let ioapics = unsafe { slice::from_raw_parts((&raw const IOAPICS).cast::<IoApic>(), count) };
+    ioapics
+        .iter()
+        .find(|ioapic| ioapic.handles(gsi))
+        .ok_or(IoApicError::NoIoApic(gsi))
+}
+
+const fn legacy_identity_map() -> [Gsi; LEGACY_IRQS] {
+    let mut gsis = [Gsi::isa(0); LEGACY_IRQS];
+    let mut irq = 0;
+    while irq < LEGACY_IRQS {
+        gsis[irq] = Gsi::isa(irq as u32);
+        irq += 1;
+    }
+    gsis
+}
@@ -0,0 +1,50 @@
+//! Dispatch of external device interrupts to handlers registered at run time.
+//!
+//! Vectors FIRST_VECTOR.. have fixed IDT stubs that all land in dispatch(). Routing a
+//! device interrupt allocates one of them and stores its handler here.
+
+use core::mem::transmute;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::causality::context;
+
+use super::apic;
+use super::interrupts::{self, InterruptStackFrame};
+
+pub const FIRST_VECTOR: u8 = 0x40;
+pub const VECTOR_COUNT: usize = 32;
+
+/// Device interrupt handler. It runs inside the interrupt's causal context, so events
+/// it records through `causality::context` are caused by the `IrqEntry` event.
+pub type Handler = fn(vector: u8);
+
+const NO_HANDLER: usize = 0;
+
+static HANDLERS: [AtomicUsize; VECTOR_COUNT] = [const { AtomicUsize::new(NO_HANDLER) }; VECTOR_COUNT];
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+/// Reserve a vector for `handler`, or None once all VECTOR_COUNT are taken.
+pub fn allocate(handler: Handler) -> Option<u8> {
+    let slot = NEXT_SLOT.fetch_add(1, Ordering::Relaxed);
+    if slot >= VECTOR_COUNT {
+        return None;
+    }
+
+    HANDLERS[slot].store(handler as usize, Ordering::Release);
+    Some(FIRST_VECTOR + slot as u8)
+}
+
+/// Record the interrupt, run its handler and acknowledge it at the local APIC.
+pub fn dispatch(vector: u8, frame: &InterruptStackFrame) {
+    let entry = interrupts::record_irq_entry(vector, frame);
+
+    let handler = HANDLERS[(vector - FIRST_VECTOR) as usize].load(Ordering::Acquire);
+    if handler != NO_HANDLER {
+        let handler: Handler = unsafe { transmute::<usize, Handler>(handler) };
+        let _irq = context::push(entry);
+        handler(vector);
+    }
+
+    apic::eoi();
+    interrupts::record_irq_exit(vector, entry, frame);
+}
@@ -33,3 +33,14 @@ pub fn read_cr3() -> u64 {
     }
     value
 }
+
+/// Load `pml4_phys` as the root page table, flushing every non-global TLB entry.
+pub fn write_cr3(pml4_phys: u64) {
+    unsafe {
+        asm!(
+            "mov cr3, {}",
+            in(reg) pml4_phys,
+            options(nostack, preserves_flags)
+        );
+    }
+}
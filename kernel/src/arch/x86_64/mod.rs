@@ -1,7 +1,17 @@
+pub mod apic;
 pub mod cpu;
 pub mod gdt;
 pub mod idt;
 pub mod interrupts;
+pub mod ioapic;
+pub mod irq;
 pub mod mmu;
+pub mod msr;
+pub mod pic;
+pub mod pit;
+pub mod port;
+pub mod ps2;
 pub mod serial;
+pub mod smp;
 pub mod tss;
+pub mod tsc;
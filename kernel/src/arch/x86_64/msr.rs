@@ -0,0 +1,42 @@
+//! x86_64 model-specific register access.
+
+use core::arch::asm;
+
+pub const IA32_APIC_BASE: u32 = 0x1b;
+pub const IA32_GS_BASE: u32 = 0xc000_0101;
+pub const IA32_KERNEL_GS_BASE: u32 = 0xc000_0102;
+pub const IA32_EFER: u32 = 0xc000_0080;
+/// First of the x2APIC register MSRs; register `offset` of the xAPIC page lives at
+/// `X2APIC_BASE + offset / 16`.
+pub const X2APIC_BASE: u32 = 0x800;
+
+#[inline]
+pub fn rdmsr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+
+    unsafe {
+        asm!(
+            "rdmsr",
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    ((high as u64) << 32) | low as u64
+}
+
+#[inline]
+pub fn wrmsr(msr: u32, value: u64) {
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags)
+        );
+    }
+}
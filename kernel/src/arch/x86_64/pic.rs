@@ -0,0 +1,45 @@
+//! Legacy 8259 programmable interrupt controllers.
+//!
+//! Only touched to get them out of the way of the local APIC: both chips are remapped
+//! off the exception vectors, then every line is masked.
+
+use super::port::outb;
+
+/// First vector of the remapped master PIC; the slave follows at +8. A spurious IRQ
+/// raised by a masked PIC lands here instead of on an exception vector.
+pub const VECTOR_OFFSET: u8 = 0x20;
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xa0;
+const SLAVE_DATA: u16 = 0xa1;
+/// Unused port written to give the PICs time between initialization words.
+const IO_WAIT: u16 = 0x80;
+
+const ICW1_INIT: u8 = 0x10;
+const ICW1_ICW4: u8 = 0x01;
+const ICW4_8086: u8 = 0x01;
+const SLAVE_ON_IRQ2: u8 = 1 << 2;
+const SLAVE_CASCADE_ID: u8 = 2;
+const MASK_ALL: u8 = 0xff;
+
+/// Remap both PICs to VECTOR_OFFSET and mask all of their lines.
+pub fn disable() {
+    write(MASTER_COMMAND, ICW1_INIT | ICW1_ICW4);
+    write(SLAVE_COMMAND, ICW1_INIT | ICW1_ICW4);
+    write(MASTER_DATA, VECTOR_OFFSET);
+    write(SLAVE_DATA, VECTOR_OFFSET + 8);
+    write(MASTER_DATA, SLAVE_ON_IRQ2);
+    write(SLAVE_DATA, SLAVE_CASCADE_ID);
+    write(MASTER_DATA, ICW4_8086);
+    write(SLAVE_DATA, ICW4_8086);
+
+    outb(MASTER_DATA, MASK_ALL);
+    outb(SLAVE_DATA, MASK_ALL);
+}
+
+fn write(port: u16, value: u8) {
+    outb(port, value);
+    outb(IO_WAIT, 0);
+}
+
@@ -0,0 +1,40 @@
+//! 8253/8254 programmable interval timer, used only as a fixed-frequency reference
+//! for calibrating other clocks.
+//!
+//! Channel 2 is used because its gate and output are software visible through the
+//! PC speaker control port, so a countdown can be polled without interrupts.
+
+use super::port::{inb, outb};
+
+pub const FREQUENCY_HZ: u64 = 1_193_182;
+
+const CHANNEL2_DATA: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+const SPEAKER_CONTROL: u16 = 0x61;
+
+const GATE2: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const OUT2: u8 = 1 << 5;
+
+// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary
+const CHANNEL2_ONESHOT: u8 = 0b1011_0000;
+
+/// Number of PIT ticks in `micros` microseconds.
+pub const fn ticks_for_us(micros: u64) -> u16 {
+    (FREQUENCY_HZ * micros / 1_000_000) as u16
+}
+
+/// Start a one-shot countdown of `ticks` on channel 2. Counting begins as soon as this
+/// returns; poll expired() for the end.
+pub fn start_oneshot(ticks: u16) {
+    let control = inb(SPEAKER_CONTROL);
+    outb(SPEAKER_CONTROL, (control & !SPEAKER_ENABLE) | GATE2);
+
+    outb(COMMAND, CHANNEL2_ONESHOT);
+    outb(CHANNEL2_DATA, (ticks & 0xff) as u8);
+    outb(CHANNEL2_DATA, (ticks >> 8) as u8);
+}
+
+pub fn expired() -> bool {
+    inb(SPEAKER_CONTROL) & OUT2 != 0
+}
@@ -0,0 +1,18 @@
+//! PS/2 controller. Keyboard bytes are only drained so IRQ 1 keeps firing; the
+//! interrupts themselves show up as causality events.
+
+use super::ioapic::{self, IoApicError};
+use super::port::inb;
+
+const DATA: u16 = 0x60;
+const KEYBOARD_IRQ: u8 = 1;
+
+/// Route the keyboard interrupt to the local APIC with ID `destination`. Returns the
+/// vector it was given.
+pub fn init(destination: u32) -> Result<u8, IoApicError> {
+    ioapic::route(ioapic::legacy_irq(KEYBOARD_IRQ), destination, keyboard_interrupt)
+}
+
+fn keyboard_interrupt(_vector: u8) {
+    let _ = inb(DATA);
+}
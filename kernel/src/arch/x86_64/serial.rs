@@ -5,10 +5,17 @@
 //! at the standard COM1 base address (0x3F8).
 //!
 //! This driver uses x86_64 port I/O instructions.
+//!
+//! The port is shared by `println!` and the binary causality stream, so it sits behind a
+//! lock held for a whole message or record; output from different cores never
+//! interleaves.
 
-use core::arch::asm;
 use core::fmt;
 
+use crate::sync::{SpinLock, SpinLockGuard};
+
+use super::port::{inb, outb};
+
 const COM1_BASE: u16 = 0x3f8;
 
 // UART register offsets from base
@@ -19,6 +26,12 @@ const LCR: u16 = 3; // Line control
 const MCR: u16 = 4; // Modem control
 const LSR: u16 = 5; // Line status
 
+/// Spins the panic path waits for another holder before taking the port anyway.
+const PANIC_LOCK_SPINS: usize = 100_000_000;
+
+static COM1: SpinLock<Serial> = SpinLock::new(Serial);
+
+/// COM1, reached through lock().
 pub struct Serial;
 
 /// Initialize COM1 for 115200 baud, 8N1 (8 data bits, no parity, 1 stop bit).
@@ -34,54 +47,45 @@ pub fn init() {
     outb(COM1_BASE + MCR, 0x0b); // Enable DTR, RTS, OUT2
 }
 
-impl fmt::Write for Serial {
-    fn write_str(&mut self, input: &str) -> fmt::Result {
-        write_str(input.as_bytes());
-        Ok(())
+impl Serial {
+    pub fn write_byte(&mut self, value: u8) {
+        // Wait for transmit buffer empty (bit 5 of LSR)
+        loop {
+            let in_byte = inb(COM1_BASE + LSR);
+            if in_byte & 0x20 != 0 {
+                break;
+            }
+        }
+        outb(COM1_BASE, value);
     }
-}
 
-pub fn write_byte(value: u8) {
-    // Wait for transmit buffer empty (bit 5 of LSR)
-    loop {
-        let in_byte = inb(COM1_BASE + LSR);
-        if in_byte & 0x20 != 0 {
-            break;
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
         }
     }
-    outb(COM1_BASE, value);
 }
 
-pub fn write_str(string: &[u8]) {
-    for &byte in string {
-        write_byte(byte);
+impl fmt::Write for Serial {
+    fn write_str(&mut self, input: &str) -> fmt::Result {
+        self.write_bytes(input.as_bytes());
+        Ok(())
     }
 }
 
-#[inline]
-fn outb(port: u16, value: u8) {
-    unsafe {
-        asm!(
-            "out dx, al",
-            in("dx") port,
-            in("al") value,
-            options(nomem, nostack, preserves_flags)
-        );
-    }
+/// Hold COM1 for everything written through the guard.
+pub fn lock() -> SpinLockGuard<'static, Serial> {
+    COM1.lock()
 }
 
-#[inline]
-fn inb(port: u16) -> u8 {
-    let value: u8;
-
-    unsafe {
-        asm!(
-            "in al, dx",
-            in("dx") port,
-            out("al") value,
-            options(nomem, nostack, preserves_flags)
-        );
+/// Hold COM1 on the panic path. A holder that does not finish within a bounded wait is
+/// assumed to be this CPU's own interrupted code or a stuck CPU, and is overridden.
+pub fn lock_for_panic() -> SpinLockGuard<'static, Serial> {
+    for _ in 0..PANIC_LOCK_SPINS {
+        if let Some(serial) = COM1.try_lock() {
+            return serial;
+        }
+        core::hint::spin_loop();
     }
-
-    value
+    unsafe { COM1.force_lock() }
 }
@@ -0,0 +1,142 @@
+//! Application processor bring-up through the Limine MP request.
+//!
+//! The bootstrap processor maps a stack for every AP before releasing it, so APs never
+//! touch the frame allocator or page tables while starting. Each AP arrives on the
+//! bootloader's page tables and switches to the kernel's before using its stack.
+//!
+//! APs beyond MAX_CPUS are not left in the bootloader's wait loop, which runs from
+//! bootloader memory that is reclaimed later. They are released into a halt loop on the
+//! kernel's page tables instead.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use ::limine::mp::Cpu as LimineCpu;
+
+use crate::boot::limine;
+use crate::causality::context::{self, ContextToken};
+use crate::causality::types::{EventData, EventKind, RootCause};
+use crate::mm::{address_space, stack};
+
+use super::cpu::{self, MAX_CPUS};
+use super::{apic, idt};
+
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+static PARKED: AtomicUsize = AtomicUsize::new(0);
+/// Local APIC ID, plus one, of the processor that stopped the others; 0 if none has.
+static STOPPED_BY: AtomicU32 = AtomicU32::new(0);
+/// Stack of the parked APs. They never push to it, so they can share it; it only keeps
+/// their stack pointer out of bootloader memory.
+static mut PARK_STACK: ParkStack = ParkStack([0; 64]);
+/// Boot context of the bootstrap processor, written before any AP is released.
+static mut BSP_BOOT_CONTEXT: Option<ContextToken> = None;
+
+#[repr(C, align(16))]
+struct ParkStack([u8; 64]);
+
+/// Start every application processor the bootloader reported, up to MAX_CPUS in total,
+/// and wait until each has recorded its boot event. The rest are parked, and waited for
+/// until they have left bootloader memory. Returns how many were started.
+///
+/// Must run on the bootstrap processor inside its boot context, after cpu, idt and
+/// causality are initialized.
+pub fn start_application_processors(hhdm_offset: u64) -> usize {
+    unsafe { BSP_BOOT_CONTEXT = context::capture() };
+
+    let (mut started, mut parked) = (0, 0);
+    for ap in limine::application_processors() {
+        if started + 1 >= MAX_CPUS {
+            ap.goto_address.write(ap_park);
+            parked += 1;
+            continue;
+        }
+
+        let stack_top = stack::allocate_stack(started + 1, hhdm_offset)
+            .expect("Application processor stack should be successfully allocated and mapped");
+        ap.extra.store(stack_top, Ordering::Relaxed);
+        // Publishes the stack and boot context before the AP jumps.
+        ap.goto_address.write(ap_entry);
+        started += 1;
+    }
+
+    while ONLINE.load(Ordering::Acquire) < started || PARKED.load(Ordering::Acquire) < parked {
+        core::hint::spin_loop();
+    }
+
+    started
+}
+
+/// Halt every other started processor with an NMI, so nothing else records events or
+/// writes to serial while a panic is reported. Parked APs are left alone; they never
+/// loaded the kernel's IDT.
+///
+/// Only the first caller stops the others. Any other processor that calls it halts,
+/// as the first one's NMI is on its way; the first one may call it again.
+pub fn stop_other_processors() {
+    // APs are started after the local APIC is initialized, so none are up without it.
+    if ONLINE.load(Ordering::Acquire) == 0 {
+        return;
+    }
+
+    let own = apic::id();
+    match STOPPED_BY.compare_exchange(0, own + 1, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => {}
+        Err(stopper) if stopper == own + 1 => return,
+        Err(_) => cpu::halt(),
+    }
+
+    for apic_id in cpu::started_apic_ids().filter(|&apic_id| apic_id != own) {
+        apic::send_nmi(apic_id);
+    }
+}
+
+/// Whether another processor has started stopping this one to report a panic.
+pub fn stopped_by_other() -> bool {
+    let stopper = STOPPED_BY.load(Ordering::Acquire);
+    stopper != 0 && stopper != apic::id() + 1
+}
+
+unsafe extern "C" fn ap_entry(ap: &LimineCpu) -> ! {
+    address_space::activate();
+    cpu::switch_stack(ap.extra.load(Ordering::Relaxed), ap_main);
+}
+
+/// Entry of the APs beyond MAX_CPUS: switch to the kernel's page tables and stack, then
+/// halt for good with interrupts disabled.
+unsafe extern "C" fn ap_park(_ap: &LimineCpu) -> ! {
+    address_space::activate();
+
+    let stack_top = unsafe { (&raw mut PARK_STACK).add(1) } as u64;
+    // Counted only once off the bootloader stack, as the BSP may reclaim it right after.
+    unsafe {
+        asm!(
+            "mov rsp, {}",
+            "lock inc qword ptr [{}]",
+            "2:",
+            "cli",
+            "hlt",
+            "jmp 2b",
+            in(reg) stack_top,
+            in(reg) PARKED.as_ptr(),
+            options(noreturn),
+        );
+    }
+}
+
+extern "C" fn ap_main() -> ! {
+    cpu::init();
+    idt::load();
+    apic::init_ap();
+
+    // The AP's boot event is caused by the BSP's; everything after descends from it.
+    let bsp_boot = unsafe { BSP_BOOT_CONTEXT }.map(context::resume);
+    let boot = context::emit(EventKind::Boot, RootCause::Boot, EventData::None);
+    drop(bsp_boot);
+    let _boot_context = context::push(boot);
+
+    ONLINE.fetch_add(1, Ordering::Release);
+
+    loop {
+        unsafe { asm!("hlt", options(nomem, nostack)) };
+    }
+}
@@ -0,0 +1,99 @@
+//! Time stamp counter used to stamp causality events.
+//!
+//! Timestamps are advisory: the event spec forbids inferring order from them, and TSCs
+//! are only comparable across cores when the CPU reports an invariant TSC.
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::acpi::fadt::{self, Fadt, PM_TIMER_HZ};
+
+use super::pit;
+
+const EXTENDED_MAX_LEAF: u32 = 0x8000_0000;
+const ADVANCED_POWER_MGMT_LEAF: u32 = 0x8000_0007;
+const INVARIANT_TSC: u32 = 1 << 8;
+
+const CALIBRATION_US: u64 = 10_000;
+const CALIBRATION_ROUNDS: usize = 3;
+
+static FREQUENCY_HZ: AtomicU64 = AtomicU64::new(0);
+static IS_INVARIANT: AtomicBool = AtomicBool::new(false);
+
+/// Detect an invariant TSC and measure its frequency against the ACPI PM timer, or the
+/// PIT if the FADT describes no PM timer. acpi::init() must have run.
+pub fn init() {
+    IS_INVARIANT.store(detect_invariant(), Ordering::Relaxed);
+
+    let frequency = fadt::fadt()
+        .and_then(|fadt| calibrate_pm_timer(&fadt))
+        .unwrap_or_else(calibrate);
+    FREQUENCY_HZ.store(frequency, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+pub fn is_invariant() -> bool {
+    IS_INVARIANT.load(Ordering::Relaxed)
+}
+
+/// Calibrated TSC frequency, 0 before init().
+pub fn frequency_hz() -> u64 {
+    FREQUENCY_HZ.load(Ordering::Relaxed)
+}
+
+/// TSC cycles per nanosecond as unsigned Q32.32 fixed point.
+pub fn cycles_per_ns_q32() -> u64 {
+    (((frequency_hz() as u128) << 32) / 1_000_000_000) as u64
+}
+
+fn detect_invariant() -> bool {
+    let max_leaf = __cpuid(EXTENDED_MAX_LEAF).eax;
+    if max_leaf < ADVANCED_POWER_MGMT_LEAF {
+        return false;
+    }
+    __cpuid(ADVANCED_POWER_MGMT_LEAF).edx & INVARIANT_TSC != 0
+}
+
+/// Shortest of several PM-timer-timed windows, like calibrate(). None if the PM timer
+/// cannot be read. The PM timer is free-running, so a window ends at the first count
+/// at least CALIBRATION_US past its start, modulo the counter width.
+fn calibrate_pm_timer(fadt: &Fadt) -> Option<u64> {
+    let ticks = (PM_TIMER_HZ * CALIBRATION_US / 1_000_000) as u32;
+    let mask = fadt.pm_timer_mask();
+    let mut best = u64::MAX;
+
+    for _ in 0..CALIBRATION_ROUNDS {
+        let start_count = fadt.read_pm_timer().ok()?;
+        let start = read();
+        while fadt.read_pm_timer().ok()?.wrapping_sub(start_count) & mask < ticks {
+            core::hint::spin_loop();
+        }
+        let elapsed = read() - start;
+        best = best.min(elapsed);
+    }
+
+    Some(best * PM_TIMER_HZ / ticks as u64)
+}
+
+/// Shortest of several PIT-timed windows, which is the one least inflated by SMIs or
+/// virtualization exits.
+fn calibrate() -> u64 {
+    let ticks = pit::ticks_for_us(CALIBRATION_US);
+    let mut best = u64::MAX;
+
+    for _ in 0..CALIBRATION_ROUNDS {
+        pit::start_oneshot(ticks);
+        let start = read();
+        while !pit::expired() {
+            core::hint::spin_loop();
+        }
+        let elapsed = read() - start;
+        best = best.min(elapsed);
+    }
+
+    best * 1_000_000 / CALIBRATION_US
+}
@@ -1,6 +1,10 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
 use limine::BaseRevision;
-use limine::request::{HhdmRequest, MemoryMapRequest};
+use limine::mp::Cpu;
+use limine::request::{ExecutableAddressRequest, HhdmRequest, MemoryMapRequest, MpRequest, RsdpRequest};
 use limine::{memory_map::Entry, memory_map::EntryType};
+use crate::mm::memory_map;
 use crate::mm::types::{MemoryRegion, RegionType};
 
 #[used]
@@ -15,36 +19,94 @@ pub static MEMORY_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
 #[unsafe(link_section = ".limine_reqs")]
 pub static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
 
-static mut MEMORY_REGIONS: [MemoryRegion; 64] = [MemoryRegion::empty(); 64];
-static mut REGION_COUNT: usize = 0;
+#[used]
+#[unsafe(link_section = ".limine_reqs")]
+pub static MP_REQUEST: MpRequest = MpRequest::new();
+
+#[used]
+#[unsafe(link_section = ".limine_reqs")]
+pub static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+
+#[used]
+#[unsafe(link_section = ".limine_reqs")]
+pub static EXECUTABLE_ADDRESS_REQUEST: ExecutableAddressRequest = ExecutableAddressRequest::new();
 
-pub fn build_kernel_memory_map() -> &'static [MemoryRegion] {
-    unsafe {
-        let raw_entries = get_raw_entries();
-        let mut idx = 0;
+static mut MEMORY_MAP: &[MemoryRegion] = &[];
+static HHDM_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// Physical address of the RSDP, 0 if the firmware has none.
+static RSDP_ADDRESS: AtomicU64 = AtomicU64::new(0);
+static KERNEL_PHYSICAL_BASE: AtomicU64 = AtomicU64::new(0);
+static KERNEL_VIRTUAL_BASE: AtomicU64 = AtomicU64::new(0);
+/// Set once bootloader memory may be reclaimed; responses must not be read after.
+static RELEASED: AtomicBool = AtomicBool::new(false);
 
-        for entry in raw_entries {
-            let region = MemoryRegion {
-                base: entry.base,
-                length: entry.length,
-                kind: get_entry_type(entry.entry_type),
-            };
+/// Copy the memory map, HHDM offset, RSDP and kernel addresses out of the bootloader
+/// responses, so they stay available once bootloader memory is reclaimed. Must run first.
+pub fn init() {
+    let hhdm_offset = HHDM_REQUEST
+        .get_response()
+        .expect("Bootloader should provide hhdm offset")
+        .offset();
+    HHDM_OFFSET.store(hhdm_offset, Ordering::Relaxed);
 
-            MEMORY_REGIONS[idx] = region;
-            idx += 1;
-        }
+    let raw_regions = get_raw_entries().iter().map(|entry| MemoryRegion {
+        base: entry.base,
+        length: entry.length,
+        kind: get_entry_type(entry.entry_type),
+    });
+    unsafe { MEMORY_MAP = memory_map::build(raw_regions, hhdm_offset) };
 
-        REGION_COUNT = idx;
-        &MEMORY_REGIONS[0..REGION_COUNT]
+    if let Some(response) = RSDP_REQUEST.get_response() {
+        RSDP_ADDRESS.store(response.address() as u64, Ordering::Relaxed);
     }
+
+    let kernel_address = EXECUTABLE_ADDRESS_REQUEST
+        .get_response()
+        .expect("Bootloader should provide the kernel address");
+    KERNEL_PHYSICAL_BASE.store(kernel_address.physical_base(), Ordering::Relaxed);
+    KERNEL_VIRTUAL_BASE.store(kernel_address.virtual_base(), Ordering::Relaxed);
+}
+
+/// The kernel's normalized copy of the bootloader memory map.
+pub fn memory_map() -> &'static [MemoryRegion] {
+    unsafe { MEMORY_MAP }
 }
 
 pub fn get_hhdm_offset() -> u64 {
-    let response = HHDM_REQUEST
-        .get_response()
-        .expect("Bootloader should provide hhdm offset");
+    HHDM_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Physical address of the ACPI RSDP, if the firmware has one.
+pub fn get_rsdp_address() -> Option<u64> {
+    match RSDP_ADDRESS.load(Ordering::Relaxed) {
+        0 => None,
+        address => Some(address),
+    }
+}
+
+/// Physical and virtual address the kernel image was loaded at.
+pub fn get_kernel_address() -> (u64, u64) {
+    (KERNEL_PHYSICAL_BASE.load(Ordering::Relaxed), KERNEL_VIRTUAL_BASE.load(Ordering::Relaxed))
+}
+
+/// Declare that no bootloader response will be read again, so the memory holding them
+/// can be reclaimed.
+pub fn release() {
+    RELEASED.store(true, Ordering::Relaxed);
+}
 
-    response.offset()
+/// Application processors started by the bootloader and parked until their goto
+/// address is written. Empty if the bootloader did not answer the MP request.
+pub fn application_processors() -> impl Iterator<Item = &'static Cpu> {
+    assert!(!RELEASED.load(Ordering::Relaxed), "Bootloader responses read after release");
+    MP_REQUEST.get_response().into_iter().flat_map(|response| {
+        let bsp_lapic_id = response.bsp_lapic_id();
+        response
+            .cpus()
+            .iter()
+            .copied()
+            .filter(move |cpu| cpu.lapic_id != bsp_lapic_id)
+    })
 }
 
 fn get_raw_entries() -> &'static [&'static Entry] {
@@ -1,88 +1,361 @@
-use crate::arch::x86_64::cpu;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering, fence};
 
-use super::types::{Cause, Event, EventData, EventId, EventKind};
+use crate::arch::x86_64::{cpu, tsc};
+
+use super::types::{Cause, Event, EventData, EventId, EventKind, RootCause};
+
+pub use crate::arch::x86_64::cpu::MAX_CPUS;
 
-/// Fixed per-CPU slot count and IDs are indexed by APIC ID for now.
-pub const MAX_CPUS: usize = 16;
 pub const CAPACITY: usize = 4096;
 
-static mut EVENT_RING_BUFFERS: [EventRingBuffer; MAX_CPUS] = [EventRingBuffer::new(); MAX_CPUS];
-static mut IS_INITIALIZED: bool = false;
+/// Slot stamp for a slot that has never held an event.
+const EMPTY_STAMP: u64 = 0;
+/// Set in a slot stamp while the event for that stamp is being written.
+const WRITING: u64 = 1 << 63;
+/// Marker for "no undrained events have been overwritten since the last loss event".
+const NO_LOSS: u64 = u64::MAX;
+
+static EVENT_RING_BUFFERS: [EventRingBuffer; MAX_CPUS] = [const { EventRingBuffer::new() }; MAX_CPUS];
+static IS_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
-#[derive(Clone, Copy)]
-struct EventRingBuffer {
-    ring_buffer: [Option<Event>; CAPACITY],
-    write_idx: usize,
-    next_sequence: u64,
-    count: usize,
+/// Result of a drain or read: how many leading entries of the output slice were
+/// filled and where the next read should resume.
+#[derive(Clone, Copy, Debug)]
+pub struct DrainBatch {
+    pub count: usize,
+    pub next_sequence: u64,
 }
 
+/// What a reader found in the slot for a given sequence.
+enum SlotRead {
+    Event(Event),
+    /// The sequence is reserved but its event is not committed yet.
+    Pending,
+    /// The event was overwritten by a later sequence.
+    Overwritten,
+}
+
+/// A single ring slot guarded by a per-slot sequence lock.
+///
+/// `stamp` is `sequence + 1` once the event for `sequence` is fully written and
+/// `(sequence + 1) | WRITING` while it is being written, so a reader that observes the
+/// same committed stamp before and after copying the event knows the copy is not torn,
+/// and can tell an uncommitted sequence from an overwritten one.
+struct Slot {
+    stamp: AtomicU64,
+    event: UnsafeCell<MaybeUninit<Event>>,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            stamp: AtomicU64::new(EMPTY_STAMP),
+            event: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Only the core that reserved `event`'s sequence writes its slot.
+    fn write(&self, event: Event) {
+        let stamp = event.id.sequence() + 1;
+        self.stamp.store(stamp | WRITING, Ordering::Relaxed);
+        fence(Ordering::Release);
+        unsafe { write_volatile(self.event.get(), MaybeUninit::new(event)) };
+        self.stamp.store(stamp, Ordering::Release);
+    }
+
+    fn read(&self, sequence: u64) -> SlotRead {
+        let expected = sequence + 1;
+        let before = self.stamp.load(Ordering::Acquire);
+        if before & !WRITING < expected || before == expected | WRITING {
+            return SlotRead::Pending;
+        }
+        if before != expected {
+            return SlotRead::Overwritten;
+        }
+
+        let event = unsafe { read_volatile(self.event.get()) };
+        fence(Ordering::Acquire);
+
+        // A committed slot only changes when a later sequence claims it.
+        let after = self.stamp.load(Ordering::Relaxed);
+        if after != before {
+            return SlotRead::Overwritten;
+        }
+
+        SlotRead::Event(unsafe { event.assume_init() })
+    }
+}
+
+/// Per-core ring of events, written by its owning core and read from any core.
+///
+/// Recording is reentrant: a sequence is reserved with a single atomic add and
+/// published through its slot stamp, so an interrupt, exception or NMI that records
+/// while the same core is mid-record simply takes the next sequence. Readers stop at
+/// the first reserved but uncommitted sequence and skip overwritten ones; they never
+/// block the producer.
+///
+/// Overwriting an event the kernel drain has not consumed yet is remembered as a
+/// pending loss range and reported with an `EventKind::Loss` event as soon as the
+/// buffer has room for one.
+pub struct EventRingBuffer {
+    slots: [Slot; CAPACITY],
+    /// Sequence number the next reservation will get. Sequences below it are reserved,
+    /// not necessarily committed.
+    next_sequence: AtomicU64,
+    /// Sequence number of the first event the kernel drain has not yet consumed.
+    drain_sequence: AtomicU64,
+    /// Bounds of overwritten undrained sequences not yet reported: lowest (NO_LOSS if
+    /// none) and highest. Nested recorders may note losses out of order, hence
+    /// min/max updates.
+    lost_first: AtomicU64,
+    lost_last: AtomicU64,
+}
+
+unsafe impl Sync for EventRingBuffer {}
+
 impl EventRingBuffer {
     const fn new() -> Self {
         Self {
-            ring_buffer: [None; CAPACITY],
-            write_idx: 0,
-            next_sequence: 0,
-            count: 0,
+            slots: [const { Slot::new() }; CAPACITY],
+            next_sequence: AtomicU64::new(0),
+            drain_sequence: AtomicU64::new(0),
+            lost_first: AtomicU64::new(NO_LOSS),
+            lost_last: AtomicU64::new(0),
         }
     }
 
-    fn record(&mut self, core_id: u16, kind: EventKind, cause: Cause, data: EventData) -> EventId {
-        let event_id = EventId::new(core_id, self.next_sequence);
+    fn record(&self, core_id: u16, kind: EventKind, cause: Cause, data: EventData) -> EventId {
+        // Report the previous loss only once it will not itself overwrite undrained events.
+        if self.lost_first.load(Ordering::Relaxed) != NO_LOSS
+            && !self.overwrites_undrained(self.next_sequence.load(Ordering::Relaxed))
+            && let Some((first, last)) = self.take_loss()
+        {
+            let loss = EventData::Loss {
+                core: core_id,
+                first_sequence: first,
+                last_sequence: last,
+            };
+            self.commit(core_id, EventKind::Loss, Cause::Root(RootCause::Overflow), loss);
+        }
+
+        self.commit(core_id, kind, cause, data)
+    }
+
+    fn commit(&self, core_id: u16, kind: EventKind, cause: Cause, data: EventData) -> EventId {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        if self.overwrites_undrained(sequence) {
+            self.note_loss(sequence - CAPACITY as u64);
+        }
+
+        let event_id = EventId::new(core_id, sequence);
         let event = Event {
             id: event_id,
-            kind: kind,
-            cause: cause,
-            data: data,
+            timestamp: tsc::read(),
+            kind,
+            cause,
+            data,
         };
 
-        self.ring_buffer[self.write_idx] = Some(event);
+        self.slots[slot_index(sequence)].write(event);
+
+        event_id
+    }
+
+    /// Whether writing `sequence` would evict an event the drain has not consumed.
+    fn overwrites_undrained(&self, sequence: u64) -> bool {
+        let capacity = CAPACITY as u64;
+        sequence >= capacity && sequence - capacity >= self.drain_sequence.load(Ordering::Acquire)
+    }
 
-        self.write_idx = (self.write_idx + 1) % CAPACITY;
-        self.next_sequence += 1;
+    fn note_loss(&self, evicted: u64) {
+        self.lost_first.fetch_min(evicted, Ordering::Relaxed);
+        self.lost_last.fetch_max(evicted, Ordering::Relaxed);
+    }
 
-        if self.count < CAPACITY {
-            self.count += 1;
+    /// Claim the pending loss range. A loss noted by a nested recorder between the two
+    /// swaps may be reported twice, but is never dropped.
+    fn take_loss(&self) -> Option<(u64, u64)> {
+        let first = self.lost_first.swap(NO_LOSS, Ordering::Relaxed);
+        if first == NO_LOSS {
+            return None;
         }
+        let last = self.lost_last.swap(0, Ordering::Relaxed);
+        Some((first, last.max(first)))
+    }
 
-        event_id
+    /// Record loss events for what a dump of the `limit` newest events will not re-send:
+    /// overflow loss not reported yet, and events from `unsent` on that are older than
+    /// the dump window. Both events land inside the window, which must hold at least two
+    /// events.
+    fn declare_dump_truncation(&self, core_id: u16, unsent: u64, limit: usize) {
+        let pending = self.take_loss();
+        let head = self.next_sequence.load(Ordering::Acquire);
+        let recorded = pending.is_some() as u64 + 1;
+        let window_start = (head + recorded).saturating_sub(limit as u64);
+
+        if let Some((first, last)) = pending {
+            let loss = EventData::Loss {
+                core: core_id,
+                first_sequence: first,
+                last_sequence: last,
+            };
+            self.commit(core_id, EventKind::Loss, Cause::Root(RootCause::Overflow), loss);
+        }
+        if window_start > unsent {
+            let loss = EventData::Loss {
+                core: core_id,
+                first_sequence: unsent,
+                last_sequence: window_start - 1,
+            };
+            self.commit(core_id, EventKind::Loss, Cause::Root(RootCause::Panic), loss);
+        }
+    }
+
+    /// Copy events with sequence >= `from` into `out` in sequence order, stopping at
+    /// the first uncommitted sequence. Events that have already been overwritten are
+    /// skipped.
+    fn read_from(&self, from: u64, out: &mut [Option<Event>]) -> DrainBatch {
+        let head = self.next_sequence.load(Ordering::Acquire);
+        let oldest = head.saturating_sub(CAPACITY as u64);
+        let mut sequence = from.max(oldest);
+        let mut count = 0;
+
+        while sequence < head && count < out.len() {
+            match self.slots[slot_index(sequence)].read(sequence) {
+                SlotRead::Event(event) => {
+                    out[count] = Some(event);
+                    count += 1;
+                }
+                SlotRead::Pending => break,
+                SlotRead::Overwritten => {}
+            }
+            sequence += 1;
+        }
+
+        DrainBatch {
+            count,
+            next_sequence: sequence,
+        }
+    }
+
+    /// Visit up to `limit` retained events from newest to oldest without moving the
+    /// drain cursor. Uncommitted sequences are skipped.
+    fn for_each_newest(&self, limit: usize, mut f: impl FnMut(&Event)) {
+        let head = self.next_sequence.load(Ordering::Acquire);
+        let oldest = head.saturating_sub(CAPACITY as u64);
+        let mut visited = 0;
+
+        for sequence in (oldest..head).rev() {
+            if visited == limit {
+                break;
+            }
+            if let SlotRead::Event(event) = self.slots[slot_index(sequence)].read(sequence) {
+                f(&event);
+                visited += 1;
+            }
+        }
+    }
+
+    fn drain(&self, out: &mut [Option<Event>]) -> DrainBatch {
+        loop {
+            let cursor = self.drain_sequence.load(Ordering::Acquire);
+            let batch = self.read_from(cursor, out);
+
+            // Another drainer consumed this range first, retry from its cursor.
+            if self
+                .drain_sequence
+                .compare_exchange(cursor, batch.next_sequence, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return batch;
+            }
+        }
+    }
+}
+
+fn slot_index(sequence: u64) -> usize {
+    (sequence % CAPACITY as u64) as usize
+}
+
+/// Ring buffer of the CPU with logical index `core_id`, for its per-CPU block. init()
+/// must be called first.
+pub fn for_core(core_id: u16) -> &'static EventRingBuffer {
+    let core_idx = core_id as usize;
+    if core_idx >= MAX_CPUS {
+        panic!(
+            "Core id ({}) is greater than max number of cpus ({})",
+            core_id, MAX_CPUS
+        );
+    }
+
+    if !IS_INITIALIZED.load(Ordering::Acquire) {
+        panic!("Causality event ring buffer not initialized. Call causality::init() first");
     }
+
+    &EVENT_RING_BUFFERS[core_idx]
 }
 
 /// One-time initialization barrier to make future-extensible. Buffers array is already statically allocated.
 pub fn init() {
-    unsafe {
-        let initialized = &raw mut IS_INITIALIZED;
-        if *initialized {
-            panic!("causality::init called more than once");
-        }
-
-        *initialized = true;
+    if IS_INITIALIZED.swap(true, Ordering::AcqRel) {
+        panic!("causality::init called more than once");
     }
 }
 
+pub fn is_initialized() -> bool {
+    IS_INITIALIZED.load(Ordering::Acquire)
+}
+
 /// Record an event into the CPU's event ring buffer.
+/// Safe to call from interrupt, exception and NMI handlers, including ones that
+/// interrupt a record() on the same core.
 /// init() must be called before any record() calls.
-/// Current APIC ID must be < MAX_CPUS.
+/// cpu::init() must have run on the calling CPU.
 pub fn record(kind: EventKind, cause: Cause, data: EventData) -> EventId {
-    unsafe {
-        let core_id = cpu::current_core_id();
-        let core_idx = core_id as usize;
-        if core_idx >= MAX_CPUS {
-            panic!(
-                "Core id ({}) is greater than max number of cpus ({})",
-                core_id, MAX_CPUS
-            );
-        }
+    debug_assert_eq!(data.tag(), kind.data_tag(), "event payload does not match its kind's schema");
+    if !is_initialized() {
+        panic!("Causality event ring buffer not initialized. Call causality::init() first");
+    }
 
-        if !IS_INITIALIZED {
-            panic!("Causality event ring buffer not initialized. Call causality::init() first");
-        }
+    let cpu = cpu::current();
+    cpu.events().record(cpu.index(), kind, cause, data)
+}
 
-        let buffers = &raw mut EVENT_RING_BUFFERS;
-        let slots = &mut *buffers;
-        let buffer = &mut slots[core_idx];
+/// Id of the most recently reserved event on `core_id`, if it has recorded any. The
+/// event may still be mid-commit if the caller interrupted its recorder.
+pub fn last_event(core_id: u16) -> Option<EventId> {
+    let head = for_core(core_id).next_sequence.load(Ordering::Acquire);
+    head.checked_sub(1).map(|sequence| EventId::new(core_id, sequence))
+}
 
-        buffer.record(core_id, kind, cause, data)
-    }
+/// Consume up to `out.len()` undrained events from `core_id`'s buffer, in sequence
+/// order, advancing that core's drain cursor.
+///
+/// Safe to call from any core while `core_id` keeps recording.
+pub fn drain(core_id: u16, out: &mut [Option<Event>]) -> DrainBatch {
+    for_core(core_id).drain(out)
+}
+
+/// Copy up to `out.len()` retained events from `core_id`'s buffer whose sequence is
+/// >= `sequence`, without moving the drain cursor.
+///
+/// Safe to call from any core while `core_id` keeps recording.
+pub fn read_since(core_id: u16, sequence: u64, out: &mut [Option<Event>]) -> DrainBatch {
+    for_core(core_id).read_from(sequence, out)
+}
+
+/// Declare, with loss events on `core_id`, the events from `unsent` on that a dump of
+/// its `limit` newest events will omit. `unsent` is the first sequence not yet written
+/// out, which may be below the drain cursor.
+pub fn declare_dump_truncation(core_id: u16, unsent: u64, limit: usize) {
+    for_core(core_id).declare_dump_truncation(core_id, unsent, limit)
+}
+
+/// Visit up to `limit` retained events of `core_id`, newest first, without moving the
+/// drain cursor.
+pub fn for_each_newest(core_id: u16, limit: usize, f: impl FnMut(&Event)) {
+    for_core(core_id).for_each_newest(limit, f)
 }
@@ -0,0 +1,145 @@
+//! Per-CPU causal context: the stack of events enclosing the code currently running on
+//! a core.
+//!
+//! Code that records an event through this module is automatically `CausedBy` the
+//! innermost enclosing event. Crossing to another core is explicit: capture() a token
+//! on the source core and resume() it on the destination.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::x86_64::cpu;
+
+use super::buffer;
+use super::types::{Cause, EventData, EventId, EventKind, RootCause};
+
+/// Maximum nesting of contexts on one core.
+pub const MAX_DEPTH: usize = 32;
+
+/// Lives in the per-CPU block and is only touched by its own core. Pushes reserve their
+/// slot before writing it, so an interrupt that nests its own push/pop cannot clobber
+/// the interrupted push. An interrupt landing between the reservation and the write
+/// may see a stale current context, which is why interrupt handlers start their own
+/// chain instead.
+pub struct ContextStack {
+    entries: UnsafeCell<[EventId; MAX_DEPTH]>,
+    depth: AtomicUsize,
+}
+
+unsafe impl Sync for ContextStack {}
+
+impl ContextStack {
+    pub const fn new() -> Self {
+        Self {
+            entries: UnsafeCell::new([EventId::new(0, 0); MAX_DEPTH]),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, id: EventId) -> usize {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed);
+        if depth >= MAX_DEPTH {
+            panic!("Causal context nested deeper than {}", MAX_DEPTH);
+        }
+        unsafe { (*self.entries.get())[depth] = id };
+        depth
+    }
+
+    fn pop(&self, depth: usize) {
+        let top = self.depth.load(Ordering::Relaxed);
+        if top != depth + 1 {
+            panic!("Causal context guards dropped out of order (depth {} vs {})", top, depth + 1);
+        }
+        self.depth.store(depth, Ordering::Relaxed);
+    }
+
+    fn current(&self) -> Option<EventId> {
+        let depth = self.depth.load(Ordering::Relaxed);
+        if depth == 0 {
+            return None;
+        }
+        Some(unsafe { (*self.entries.get())[depth - 1] })
+    }
+}
+
+fn current_stack() -> &'static ContextStack {
+    cpu::current().context()
+}
+
+/// An event captured on one core so that work it triggers elsewhere can be recorded as
+/// caused by it.
+#[derive(Clone, Copy, Debug)]
+pub struct ContextToken {
+    id: EventId,
+}
+
+impl ContextToken {
+    pub const fn id(&self) -> EventId {
+        self.id
+    }
+}
+
+/// Keeps an event as the current context of this core until dropped. Guards must be
+/// dropped in reverse order of creation and never leave the core they were made on.
+pub struct ContextGuard {
+    id: EventId,
+    depth: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl ContextGuard {
+    pub const fn id(&self) -> EventId {
+        self.id
+    }
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        current_stack().pop(self.depth);
+    }
+}
+
+/// Innermost event enclosing the code running on this core.
+pub fn current() -> Option<EventId> {
+    current_stack().current()
+}
+
+/// Cause for an event recorded now: the enclosing context, or `root` outside of any.
+pub fn cause_or(root: RootCause) -> Cause {
+    match current() {
+        Some(parent) => Cause::CausedBy(parent),
+        None => Cause::Root(root),
+    }
+}
+
+/// Record an event caused by the current context, or rooted at `root` outside of any.
+pub fn emit(kind: EventKind, root: RootCause, data: EventData) -> EventId {
+    buffer::record(kind, cause_or(root), data)
+}
+
+/// Record an event like emit() and make it the current context until the guard drops.
+pub fn enter(kind: EventKind, root: RootCause, data: EventData) -> ContextGuard {
+    push(emit(kind, root, data))
+}
+
+/// Make an already recorded event the current context until the guard drops.
+pub fn push(id: EventId) -> ContextGuard {
+    let depth = current_stack().push(id);
+    ContextGuard {
+        id,
+        depth,
+        _not_send: PhantomData,
+    }
+}
+
+/// Capture the current context so it can be resumed on another core.
+pub fn capture() -> Option<ContextToken> {
+    current().map(|id| ContextToken { id })
+}
+
+/// Continue a context captured on another core: events recorded through this module
+/// while the guard lives are caused by the captured event.
+pub fn resume(token: ContextToken) -> ContextGuard {
+    push(token.id)
+}
@@ -1,4 +1,6 @@
 pub mod buffer;
-pub mod types;
+pub mod context;
+pub mod stream;
 
-pub use buffer::{init, record};
+pub use buffer::{init, is_initialized, record};
+pub use causality_core::{types, wire};
@@ -0,0 +1,116 @@
+//! Streams drained causality events out of COM1 in the wire format.
+//!
+//! Records share the serial line with `println!` output; each is written under the
+//! serial lock in one piece, and the host collector finds them by their sync word and
+//! checksum.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::arch::x86_64::serial::{self, Serial};
+use crate::arch::x86_64::{cpu, tsc};
+
+use super::buffer::{self, MAX_CPUS};
+use super::types::{Cause, Event, EventData, EventKind, RootCause};
+use super::wire::{
+    self, HEADER_FLAG_INVARIANT_TSC, HEADER_FLAG_PANIC_DUMP, HEADER_LEN, Header, RECORD_LEN,
+    RECORD_TYPE_DUMPED_EVENT, RECORD_TYPE_EVENT,
+};
+
+const BATCH_LEN: usize = 32;
+/// Per-core bound on events re-sent by a panic dump.
+const PANIC_DUMP_EVENTS: usize = 256;
+
+static PANIC_DUMPED: AtomicBool = AtomicBool::new(false);
+/// Per core, the sequence after the last event written to serial. Drained events past
+/// it were lost with the pump that drained them unless a panic dump re-sends them.
+static STREAMED: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// Emit the stream header. Must be called once before the first pump(), after the TSC
+/// has been calibrated.
+pub fn init() {
+    write_header(&mut serial::lock(), 0);
+}
+
+fn write_header(serial: &mut Serial, mut flags: u16) {
+    if tsc::is_invariant() {
+        flags |= HEADER_FLAG_INVARIANT_TSC;
+    }
+
+    let header = Header {
+        flags,
+        cycles_per_ns_q32: tsc::cycles_per_ns_q32(),
+    };
+
+    let mut bytes = [0u8; HEADER_LEN];
+    wire::encode_header(&header, &mut bytes);
+    serial.write_bytes(&bytes);
+}
+
+/// Drain every core's buffer and write the events to serial. Returns how many events
+/// were written.
+///
+/// Stops writing once a panic dump has started; the dump accounts for what is left.
+pub fn pump() -> usize {
+    let mut batch: [Option<Event>; BATCH_LEN] = [None; BATCH_LEN];
+    let mut written = 0;
+
+    for core in 0..MAX_CPUS as u16 {
+        loop {
+            let drained = buffer::drain(core, &mut batch);
+            for event in batch[..drained.count].iter().flatten() {
+                let mut serial = serial::lock();
+                if PANIC_DUMPED.load(Ordering::Acquire) {
+                    return written;
+                }
+                write_event(&mut serial, event, RECORD_TYPE_EVENT);
+                STREAMED[core as usize].store(event.id.sequence() + 1, Ordering::Release);
+                written += 1;
+            }
+
+            if drained.count < BATCH_LEN {
+                break;
+            }
+        }
+    }
+
+    written
+}
+
+/// Record a panic event caused by the faulting core's latest event, then re-send the
+/// newest events of every core, newest first, under a fresh stream header. The other
+/// cores should be stopped first, so none records while its buffer is dumped.
+///
+/// A core that panicked before cpu::init() has no buffer of its own and records no
+/// panic event. Runs at most once; a panic raised while dumping skips the dump.
+pub fn panic_dump() {
+    if PANIC_DUMPED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    if cpu::is_initialized() {
+        let core = cpu::current_core_id();
+        let cause = match buffer::last_event(core) {
+            Some(last) => Cause::CausedBy(last),
+            None => Cause::Root(RootCause::Panic),
+        };
+        buffer::record(EventKind::Panic, cause, EventData::None);
+    }
+
+    // Taken after PANIC_DUMPED is set, so a pump holding the lock writes no more than
+    // its current record, and STREAMED is final.
+    let mut serial = serial::lock_for_panic();
+    write_header(&mut serial, HEADER_FLAG_PANIC_DUMP);
+    for core in 0..MAX_CPUS as u16 {
+        let streamed = STREAMED[core as usize].load(Ordering::Acquire);
+        buffer::declare_dump_truncation(core, streamed, PANIC_DUMP_EVENTS);
+        buffer::for_each_newest(core, PANIC_DUMP_EVENTS, |event| {
+            write_event(&mut serial, event, RECORD_TYPE_DUMPED_EVENT);
+        });
+    }
+}
+
+fn write_event(serial: &mut Serial, event: &Event, record_type: u8) {
+    let mut record = [0u8; RECORD_LEN];
+    wire::encode_event(event, record_type, &mut record);
+    serial.write_bytes(&record);
+}
@@ -2,7 +2,7 @@
 macro_rules! print {
     ($fmt:expr $(, $arg:tt)*) => {{
         use ::core::fmt::Write;
-        let mut serial = $crate::arch::x86_64::serial::Serial;
+        let mut serial = $crate::arch::x86_64::serial::lock();
         let _ = ::core::write!(serial, $fmt $(, $arg)*);
     }};
 }
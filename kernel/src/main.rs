@@ -1,32 +1,50 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+mod acpi;
 mod arch;
 mod boot;
 mod causality;
 mod io;
 mod mm;
+mod sync;
 
+use core::fmt::Write;
 use core::panic::PanicInfo;
 
-use crate::arch::x86_64::{cpu, idt, serial};
+use crate::arch::x86_64::apic::{self, TimerMode};
+use crate::arch::x86_64::{cpu, idt, ioapic, ps2, serial, smp, tsc};
 use crate::boot::limine;
 use crate::causality::types::{Cause, EventData, EventKind, RootCause};
-use crate::mm::{frame, stack};
+use crate::mm::{address_space, frame, heap, memory_map, reclaim, slab, stack};
+
+const TIMER_TICK_US: u64 = 10_000;
 
 #[unsafe(no_mangle)]
 extern "C" fn kernel_entry() -> ! {
     serial::init();
     println!("Initialized serial");
 
-    let regions = limine::build_kernel_memory_map();
-    println!("Built kernel memory map");
-
+    limine::init();
+    let regions = limine::memory_map();
     let hhdm = limine::get_hhdm_offset();
-    println!("Got higher-half direct map offset");
+    println!("Copied memory map and higher-half direct map offset from bootloader");
+    memory_map::print_summary(regions);
 
     frame::init(regions, hhdm);
-    println!("Initialized frame allocator");
+    let free_frames = frame::free_frames();
+    println!("Initialized frame allocator: {} free frames", free_frames);
+
+    address_space::init(regions, hhdm);
+    println!("Switched to kernel page tables");
+
+    heap::init(hhdm).expect("Kernel heap should be successfully mapped");
+    println!("Initialized kernel heap");
+
+    slab::init(hhdm);
+    println!("Initialized slab caches");
 
     let stack_top = stack::allocate_kernel_stack(hhdm)
         .expect("Kernel stack should be successfully allocated and mapped");
@@ -38,28 +56,80 @@ extern "C" fn kernel_entry() -> ! {
 extern "C" fn kernel_main() -> ! {
     println!("Jumped to kernel stack");
 
+    // Before cpu::init(), which hands the CPU its ring buffer.
+    causality::init();
+    println!("Initialized causality module");
+
     cpu::init();
     println!("Initialized cpu (gdt + tss)");
 
     idt::init();
     println!("Initialized idt");
 
-    causality::init();
-    println!("Initialized causality module");
+    let hhdm = limine::get_hhdm_offset();
+
+    let rsdp = limine::get_rsdp_address().expect("Bootloader should provide the ACPI RSDP");
+    acpi::init(rsdp, hhdm).expect("ACPI root table should be valid");
+    println!("Validated acpi tables");
+    acpi::print_summary();
+    slab::print_summary();
+
+    tsc::init();
+    let tsc_hz = tsc::frequency_hz();
+    let tsc_invariant = tsc::is_invariant();
+    println!("Calibrated tsc: {} Hz (invariant={})", tsc_hz, tsc_invariant);
+
+    apic::init(hhdm).expect("Local APIC registers should be successfully mapped");
+    let apic_timer_hz = apic::timer_frequency_hz();
+    let x2apic = apic::is_x2apic();
+    println!("Initialized local apic: timer {} Hz (x2apic={})", apic_timer_hz, x2apic);
+
+    let ioapics = ioapic::init(hhdm).expect("I/O APICs should be successfully mapped");
+    println!("Initialized {} io apics", ioapics);
+
+    causality::stream::init();
 
-    let _ = causality::record(
+    let boot = causality::record(
         EventKind::Boot,
         Cause::Root(RootCause::Boot),
         EventData::None,
     );
-
-    loop {}
+    // Everything the boot core does from here on descends from its boot event.
+    let _boot_context = causality::context::push(boot);
+
+    let started = smp::start_application_processors(hhdm);
+    println!("Started {} application processors", started);
+
+    let bootloader_frames = reclaim::reclaim_bootloader();
+    let acpi_frames = reclaim::reclaim_acpi();
+    println!("Reclaimed {} bootloader and {} acpi frames", bootloader_frames, acpi_frames);
+
+    apic::start_timer(TimerMode::Periodic, apic::TIMER_VECTOR, TIMER_TICK_US);
+    match ps2::init(apic::id()) {
+        Ok(vector) => println!("Routed ps/2 keyboard to vector {}", vector),
+        Err(err) => println!("Could not route ps/2 keyboard: {:?}", err),
+    }
+    cpu::enable_interrupts();
+
+    loop {
+        causality::stream::pump();
+    }
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!();
-    println!("KERNEL PANIC!");
-    println!("{}", info);
+    smp::stop_other_processors();
+
+    {
+        let mut serial = serial::lock_for_panic();
+        let _ = writeln!(serial);
+        let _ = writeln!(serial, "KERNEL PANIC!");
+        let _ = writeln!(serial, "{}", info);
+    }
+
+    if causality::is_initialized() {
+        causality::stream::panic_dump();
+    }
+
     loop {}
 }
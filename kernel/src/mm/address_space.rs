@@ -0,0 +1,127 @@
+//! The kernel's own page tables, replacing the ones the bootloader built.
+//!
+//! The bootloader's tables live in bootloader-reclaimable memory and map the kernel
+//! image with whatever permissions it chose. init() builds a fresh PML4 from frames of
+//! the frame allocator: the higher-half direct map over the RAM the kernel manages, in
+//! huge pages where alignment allows, and each kernel section with the permissions its
+//! contents need, bounded by symbols from the linker script. Every CPU then loads it
+//! with activate(), after which the bootloader's tables are unused.
+//!
+//! Reserved ranges stay out of the direct map, as write-back mappings of device memory
+//! are unsafe: drivers map their registers uncached on demand, and firmware tables
+//! outside RAM are added read-only by map_firmware().
+
+use crate::arch::x86_64::{cpu, mmu};
+use crate::boot::limine;
+
+use super::page::{self, MapError, PageFlags};
+use super::types::{MemoryRegion, RegionType};
+
+const PAGE_SIZE: u64 = 4096;
+
+unsafe extern "C" {
+    static __kernel_start: u8;
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __data_end: u8;
+}
+
+/// Build the kernel's page tables and switch the calling CPU to them. Must run on the
+/// bootstrap processor after the frame allocator is initialized and before anything
+/// else is mapped.
+pub fn init(regions: &[MemoryRegion], hhdm_offset: u64) {
+    page::replace_root(hhdm_offset).expect("Kernel PML4 should be successfully allocated");
+
+    let no_execute = match cpu::has_no_execute() {
+        true => PageFlags::NO_EXECUTE,
+        false => PageFlags::empty(),
+    };
+
+    map_direct(regions, PageFlags::WRITABLE | no_execute, hhdm_offset);
+    map_kernel(no_execute, hhdm_offset);
+
+    activate();
+}
+
+/// Load the kernel's page tables on the calling CPU. No-execute is enabled first, as
+/// the tables use it where supported.
+pub fn activate() {
+    cpu::enable_no_execute();
+    mmu::write_cr3(page::root());
+}
+
+/// Map the firmware memory at `phys_addr` into the direct map, read-only, where it is
+/// not mapped already; for ACPI tables in reserved or NVS memory.
+pub fn map_firmware(phys_addr: u64, size: u64, hhdm_offset: u64) {
+    let no_execute = match cpu::has_no_execute() {
+        true => PageFlags::NO_EXECUTE,
+        false => PageFlags::empty(),
+    };
+
+    let start = phys_addr / PAGE_SIZE * PAGE_SIZE;
+    let end = (phys_addr + size).next_multiple_of(PAGE_SIZE);
+    for page_addr in (start..end).step_by(PAGE_SIZE as usize) {
+        match page::map(page_addr + hhdm_offset, page_addr, no_execute, hhdm_offset) {
+            Ok(()) | Err(MapError::AlreadyMapped) => {}
+            Err(error) => panic!("Firmware memory at {:#x} should be mapped: {:?}", page_addr, error),
+        }
+    }
+}
+
+/// Map every RAM region at `hhdm_offset` plus its physical address. Regions are widened
+/// to whole pages, and regions that touch are mapped as one range so huge pages can span
+/// them.
+fn map_direct(regions: &[MemoryRegion], flags: PageFlags, hhdm_offset: u64) {
+    let mut run: Option<(u64, u64)> = None;
+
+    let ram = |region: &&MemoryRegion| {
+        matches!(
+            region.kind,
+            RegionType::Usable | RegionType::Bootloader | RegionType::AcpiReclaimable | RegionType::Kernel
+        )
+    };
+    for region in regions.iter().filter(ram) {
+        let start = region.base / PAGE_SIZE * PAGE_SIZE;
+        let end = (region.base + region.length).next_multiple_of(PAGE_SIZE);
+        match run {
+            Some((run_start, run_end)) if start <= run_end => run = Some((run_start, run_end.max(end))),
+            _ => {
+                if let Some((run_start, run_end)) = run {
+                    map_direct_range(run_start, run_end, flags, hhdm_offset);
+                }
+                run = Some((start, end));
+            }
+        }
+    }
+
+    if let Some((run_start, run_end)) = run {
+        map_direct_range(run_start, run_end, flags, hhdm_offset);
+    }
+}
+
+fn map_direct_range(start: u64, end: u64, flags: PageFlags, hhdm_offset: u64) {
+    page::map_range(start + hhdm_offset, start, end - start, flags, hhdm_offset)
+        .expect("Higher-half direct map should be successfully mapped");
+}
+
+/// Map the kernel image section by section: text read-only and executable, rodata
+/// read-only, and the Limine requests, data and bss writable.
+fn map_kernel(no_execute: PageFlags, hhdm_offset: u64) {
+    let (physical_base, virtual_base) = limine::get_kernel_address();
+
+    let sections = [
+        (&raw const __kernel_start, &raw const __text_start, PageFlags::WRITABLE | no_execute),
+        (&raw const __text_start, &raw const __text_end, PageFlags::empty()),
+        (&raw const __rodata_start, &raw const __rodata_end, no_execute),
+        (&raw const __data_start, &raw const __data_end, PageFlags::WRITABLE | no_execute),
+    ];
+
+    for (start, end, flags) in sections {
+        let (start, end) = (start as u64, end as u64);
+        page::map_range(start, start - virtual_base + physical_base, end - start, flags, hhdm_offset)
+            .expect("Kernel sections should be successfully mapped");
+    }
+}
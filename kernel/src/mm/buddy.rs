@@ -0,0 +1,165 @@
+//! Binary buddy allocator over page frame numbers.
+//!
+//! Free blocks of 2^order frames are kept on one list per order. The lists are linked
+//! through the free frames themselves, reached through the higher-half direct map, so
+//! the only side storage is one byte per frame recording which frames head a free block.
+
+use core::ptr::null_mut;
+
+/// Largest block order: 2^MAX_ORDER frames, 1 GiB, so a block can back the largest
+/// huge page.
+pub const MAX_ORDER: usize = 18;
+
+/// Set on the first frame of a free block, combined with the block's order.
+const FREE: u8 = 0x80;
+const PAGE_SIZE: u64 = 4096;
+
+struct FreeNode {
+    prev: *mut FreeNode,
+    next: *mut FreeNode,
+}
+
+pub struct BuddyAllocator {
+    free_lists: [*mut FreeNode; MAX_ORDER + 1],
+    /// `FREE | order` for the first frame of every free block, 0 for every other frame.
+    heads: *mut u8,
+    max_pfn: usize,
+    hhdm_offset: u64,
+    free_frames: usize,
+}
+
+unsafe impl Send for BuddyAllocator {}
+
+impl BuddyAllocator {
+    /// An allocator with no frames, until replaced by new().
+    pub const fn empty() -> Self {
+        Self {
+            free_lists: [null_mut(); MAX_ORDER + 1],
+            heads: null_mut(),
+            max_pfn: 0,
+            hhdm_offset: 0,
+            free_frames: 0,
+        }
+    }
+
+    /// An allocator for frames `0..max_pfn`, all initially allocated.
+    ///
+    /// # Safety
+    /// `heads` must point to `max_pfn` writable bytes that stay reserved for the
+    /// allocator, and every frame later added must be mapped at `hhdm_offset`.
+    pub unsafe fn new(heads: *mut u8, max_pfn: usize, hhdm_offset: u64) -> Self {
+        unsafe { heads.write_bytes(0, max_pfn) };
+        Self {
+            heads,
+            max_pfn,
+            hhdm_offset,
+            ..Self::empty()
+        }
+    }
+
+    /// Hand frames `start_pfn..end_pfn` to the allocator as the largest aligned blocks
+    /// that fit, merged with free neighbours.
+    pub fn add_range(&mut self, start_pfn: usize, end_pfn: usize) {
+        let end_pfn = end_pfn.min(self.max_pfn);
+        let mut pfn = start_pfn;
+
+        while pfn < end_pfn {
+            let mut order = (pfn.trailing_zeros() as usize).min(MAX_ORDER);
+            while pfn + (1 << order) > end_pfn {
+                order -= 1;
+            }
+            self.free(pfn, order);
+            pfn += 1 << order;
+        }
+    }
+
+    /// First frame of a free block of 2^order frames, aligned to its size.
+    pub fn alloc(&mut self, order: usize) -> Option<usize> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let mut block_order = (order..=MAX_ORDER).find(|&o| !self.free_lists[o].is_null())?;
+        let pfn = self.pfn_of(self.free_lists[block_order]);
+        self.remove(pfn, block_order);
+
+        // Split, returning the upper halves until the block has the requested size.
+        while block_order > order {
+            block_order -= 1;
+            self.push(pfn + (1 << block_order), block_order);
+        }
+
+        self.free_frames -= 1 << order;
+        Some(pfn)
+    }
+
+    /// Return a block obtained from alloc() with the same order, merging it with its
+    /// buddy for as long as the buddy is free.
+    pub fn free(&mut self, pfn: usize, order: usize) {
+        if order > MAX_ORDER || !pfn.is_multiple_of(1 << order) || pfn + (1 << order) > self.max_pfn {
+            panic!("Freed block at pfn {:#x} of order {} is misaligned or out of range", pfn, order);
+        }
+        if self.head(pfn) & FREE != 0 {
+            panic!("Double free of block at pfn {:#x}", pfn);
+        }
+
+        self.free_frames += 1 << order;
+
+        let (mut pfn, mut order) = (pfn, order);
+        while order < MAX_ORDER {
+            let buddy = pfn ^ (1 << order);
+            if buddy + (1 << order) > self.max_pfn || self.head(buddy) != FREE | order as u8 {
+                break;
+            }
+            self.remove(buddy, order);
+            pfn = pfn.min(buddy);
+            order += 1;
+        }
+
+        self.push(pfn, order);
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    fn push(&mut self, pfn: usize, order: usize) {
+        let node = self.node(pfn);
+        let head = self.free_lists[order];
+        unsafe {
+            node.write(FreeNode { prev: null_mut(), next: head });
+            if !head.is_null() {
+                (*head).prev = node;
+            }
+            self.heads.add(pfn).write(FREE | order as u8);
+        }
+        self.free_lists[order] = node;
+    }
+
+    fn remove(&mut self, pfn: usize, order: usize) {
+        let node = self.node(pfn);
+        unsafe {
+            let (prev, next) = ((*node).prev, (*node).next);
+            match prev.is_null() {
+                true => self.free_lists[order] = next,
+                false => (*prev).next = next,
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+            self.heads.add(pfn).write(0);
+        }
+    }
+
+    fn head(&self, pfn: usize) -> u8 {
+        unsafe { self.heads.add(pfn).read() }
+    }
+
+    fn node(&self, pfn: usize) -> *mut FreeNode {
+        (pfn as u64 * PAGE_SIZE + self.hhdm_offset) as *mut FreeNode
+    }
+
+    fn pfn_of(&self, node: *mut FreeNode) -> usize {
+        ((node as u64 - self.hhdm_offset) / PAGE_SIZE) as usize
+    }
+}
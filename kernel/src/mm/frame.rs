@@ -1,137 +1,373 @@
+//! Physical frame allocator.
+//!
+//! Frames come from a buddy allocator seeded with the usable regions of the memory map.
+//! The original allocation bitmap is kept alongside it: in debug builds and with the
+//! `frame-hardening` feature every free is checked against it and against the usable
+//! frames, so double frees and frees of memory the allocator never managed (including
+//! its own metadata) are caught.
+//!
+//! With `frame-hardening`, freed frames are also filled with a poison pattern that is
+//! verified when they are handed out again, to catch writes through stale pointers.
+//! Every caught misuse records a `FrameFault` event and panics.
+
+use core::ops::Range;
+
+use crate::causality::{self, context};
+use crate::causality::types::{EventData, EventKind, FrameFaultReason, RootCause};
+use crate::sync::SpinLock;
+
+use super::buddy::BuddyAllocator;
 use super::types::{MemoryRegion, RegionType};
 
+pub use super::buddy::MAX_ORDER;
+
 const PAGE_SIZE: usize = 4096;
+/// Order of a 2 MiB block, the size of a huge page.
+pub const HUGE_PAGE_ORDER: usize = 9;
+/// Order of a 1 GiB block, the size of a huge page mapped from the PDPT.
+pub const GIANT_PAGE_ORDER: usize = 18;
 
-static mut BITMAP_ADDR: *mut u8 = core::ptr::null_mut();
-static mut BITMAP_PHYS_START: u64 = 0;
-static mut BITMAP_SIZE: usize = 0;
-static mut MAX_PFN: usize = 0;
+const HARDENED: bool = cfg!(feature = "frame-hardening");
+/// Whether the allocation bitmap is maintained and frees are checked.
+const CHECKED: bool = HARDENED || cfg!(debug_assertions);
 
-pub fn init(regions: &[MemoryRegion], hhdm_offset: u64) {
-    unsafe {
-        MAX_PFN = max_pfn(regions);
-        BITMAP_SIZE = (MAX_PFN + 7) / 8;
+const POISON: u64 = 0xdead_f4ee_dead_f4ee;
+/// Bytes at the start of every free frame that may hold a buddy list node instead of
+/// poison.
+const POISON_SKIP: usize = 16;
+
+static FRAMES: SpinLock<FrameAllocator> = SpinLock::new(FrameAllocator::new());
 
-        let first_usable = first_usable_region(regions, BITMAP_SIZE);
+/// Misuse caught by the checks. Handed back to the caller and reported by fault() only
+/// once FRAMES is released, so recording the event never runs under the lock.
+struct Fault {
+    pfn: usize,
+    reason: FrameFaultReason,
+}
 
-        BITMAP_PHYS_START = first_usable.base;
-        let bitmap_vaddr = BITMAP_PHYS_START + hhdm_offset;
-        BITMAP_ADDR = bitmap_vaddr as *mut u8;
+/// One bit per frame, in memory reserved for the allocator.
+struct Bitmap {
+    bits: *mut u8,
+    size: usize,
+}
 
-        for i in 0..BITMAP_SIZE {
-            BITMAP_ADDR.add(i).write(0xff);
+impl Bitmap {
+    const fn empty() -> Self {
+        Self {
+            bits: core::ptr::null_mut(),
+            size: 0,
         }
+    }
 
-        for region in regions {
-            if matches!(region.kind, RegionType::Usable) {
-                let start = start_frame(region);
-                let end = end_frame(region);
-                for frame in start..=end {
-                    if frame < MAX_PFN && !bitmap_overlaps(frame) {
-                        mark_frame_free(frame);
-                    }
+    /// Clear the bitmap at `bits`, which must have room for `size` bytes.
+    unsafe fn new(bits: *mut u8, size: usize) -> Self {
+        unsafe { bits.write_bytes(0, size) };
+        Self { bits, size }
+    }
+
+    /// Whether the bit is set; false past the end.
+    fn get(&self, pfn: usize) -> bool {
+        let (byte_idx, bit_idx) = frame_to_byte_bit(pfn);
+        if byte_idx >= self.size {
+            return false;
+        }
+        let byte = unsafe { self.bits.add(byte_idx).read() };
+        ((byte >> bit_idx) & 1) != 0
+    }
+
+    fn set(&mut self, pfn: usize, value: bool) {
+        let (byte_idx, bit_idx) = frame_to_byte_bit(pfn);
+        if byte_idx >= self.size {
+            return;
+        }
+        unsafe {
+            let byte = self.bits.add(byte_idx).read();
+            let new_byte = match value {
+                true => byte | (1 << bit_idx),
+                false => byte & !(1 << bit_idx),
+            };
+            self.bits.add(byte_idx).write(new_byte);
+        }
+    }
+}
+
+struct FrameAllocator {
+    buddy: BuddyAllocator,
+    /// Frames handed out. Only maintained when CHECKED.
+    allocated: Bitmap,
+    /// Frames the allocator manages.
+    usable: Bitmap,
+    /// Free frames holding the poison pattern. Only maintained when HARDENED.
+    poisoned: Bitmap,
+    /// Physical range holding the bitmaps and the buddy's per-frame bytes.
+    metadata_start: u64,
+    metadata_size: usize,
+    max_pfn: usize,
+    hhdm_offset: u64,
+}
+
+unsafe impl Send for FrameAllocator {}
+
+impl FrameAllocator {
+    const fn new() -> Self {
+        Self {
+            buddy: BuddyAllocator::empty(),
+            allocated: Bitmap::empty(),
+            usable: Bitmap::empty(),
+            poisoned: Bitmap::empty(),
+            metadata_start: 0,
+            metadata_size: 0,
+            max_pfn: 0,
+            hhdm_offset: 0,
+        }
+    }
+
+    fn alloc(&mut self, order: usize) -> Result<Option<u64>, Fault> {
+        let Some(pfn) = self.buddy.alloc(order) else {
+            return Ok(None);
+        };
+        for frame in pfn..pfn + (1 << order) {
+            if CHECKED {
+                assert!(!self.allocated.get(frame), "Buddy allocator handed out allocated frame {:#x}", frame);
+                self.allocated.set(frame, true);
+            }
+            if HARDENED && self.poisoned.get(frame) {
+                if !self.poison_intact(frame) {
+                    return Err(Fault { pfn: frame, reason: FrameFaultReason::UseAfterFree });
                 }
+                self.poisoned.set(frame, false);
             }
         }
+        Ok(Some((pfn * PAGE_SIZE) as u64))
     }
-}
 
-pub fn alloc() -> Option<u64> {
-    unsafe {
-        for pfn in 0..MAX_PFN {
-            if is_frame_free(pfn) {
-                mark_frame_allocated(pfn);
-                return Some((pfn * PAGE_SIZE) as u64);
+    fn free(&mut self, frame_addr: u64, order: usize) -> Result<(), Fault> {
+        let pfn = frame_addr as usize / PAGE_SIZE;
+        if pfn + (1 << order) > self.max_pfn {
+            return Err(Fault { pfn: pfn.max(self.max_pfn), reason: FrameFaultReason::ForeignFree });
+        }
+        for frame in pfn..pfn + (1 << order) {
+            if CHECKED {
+                if !self.usable.get(frame) {
+                    return Err(Fault { pfn: frame, reason: FrameFaultReason::ForeignFree });
+                }
+                if !self.allocated.get(frame) {
+                    return Err(Fault { pfn: frame, reason: FrameFaultReason::DoubleFree });
+                }
+                self.allocated.set(frame, false);
+            }
+            if HARDENED {
+                self.poison(frame);
             }
         }
+        self.buddy.free(pfn, order);
+        Ok(())
     }
-    None
-}
 
-pub fn free(frame_addr: u64) {
-    let pfn = (frame_addr as usize) / PAGE_SIZE;
-    unsafe {
-        if pfn >= MAX_PFN {
-            return;
+    /// Make every whole frame of the `kind` regions available for allocation, except
+    /// the metadata and frames already managed. Returns how many frames were added.
+    fn add_regions(&mut self, regions: &[MemoryRegion], kind: RegionType) -> usize {
+        let mut added = 0;
+
+        for region in regions.iter().filter(|region| region.kind == kind) {
+            let frames = whole_frames(region);
+            let mut run_start = None;
+            for frame in frames.start..=frames.end {
+                let addable = frame < frames.end
+                    && frame < self.max_pfn
+                    && !self.metadata_overlaps(frame)
+                    && !self.usable.get(frame);
+                match (addable, run_start) {
+                    (true, None) => run_start = Some(frame),
+                    (false, Some(start)) => {
+                        self.add_range(start, frame);
+                        added += frame - start;
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
         }
+
+        added
     }
-    mark_frame_free(pfn);
-}
 
-fn bitmap_overlaps(pfn: usize) -> bool {
-    unsafe {
-        let start = BITMAP_PHYS_START as usize / PAGE_SIZE;
-        let end = (BITMAP_PHYS_START as usize + BITMAP_SIZE - 1) / PAGE_SIZE;
+    fn add_range(&mut self, start_pfn: usize, end_pfn: usize) {
+        for frame in start_pfn..end_pfn {
+            self.usable.set(frame, true);
+        }
+        self.buddy.add_range(start_pfn, end_pfn);
+    }
+
+    fn metadata_overlaps(&self, pfn: usize) -> bool {
+        let start = self.metadata_start as usize / PAGE_SIZE;
+        let end = (self.metadata_start as usize + self.metadata_size - 1) / PAGE_SIZE;
         pfn >= start && pfn <= end
     }
-}
 
-fn first_usable_region(regions: &[MemoryRegion], bitmap_size: usize) -> &MemoryRegion {
-    regions
-        .iter()
-        .find(|r| matches!(r.kind, RegionType::Usable) && r.length as usize >= bitmap_size)
-        .expect("Bootloader should provide at least one usable region")
-}
+    fn frame_words(&self, pfn: usize) -> *mut u64 {
+        ((pfn * PAGE_SIZE) as u64 + self.hhdm_offset) as *mut u64
+    }
 
-fn frame_to_byte_bit(pfn: usize) -> (usize, usize) {
-    let byte_idx = pfn / 8;
-    let bit_idx = pfn % 8;
-    (byte_idx, bit_idx)
+    fn poison(&mut self, pfn: usize) {
+        let words = self.frame_words(pfn);
+        for i in 0..PAGE_SIZE / 8 {
+            unsafe { words.add(i).write_volatile(POISON) };
+        }
+        self.poisoned.set(pfn, true);
+    }
+
+    fn poison_intact(&self, pfn: usize) -> bool {
+        let words = self.frame_words(pfn);
+        (POISON_SKIP / 8..PAGE_SIZE / 8).all(|i| unsafe { words.add(i).read_volatile() } == POISON)
+    }
 }
 
-fn is_frame_free(pfn: usize) -> bool {
-    let (byte_idx, bit_idx) = frame_to_byte_bit(pfn);
+/// Place the allocator's metadata in the first usable region large enough to hold it,
+/// then seed the buddy allocator with every other usable frame.
+pub fn init(regions: &[MemoryRegion], hhdm_offset: u64) {
+    let mut frames = FRAMES.lock();
+
+    let max_pfn = max_pfn(regions);
+    let bitmap_size = max_pfn.div_ceil(8);
+    let metadata_size = 3 * bitmap_size + max_pfn;
+
+    let first_usable = first_usable_region(regions, metadata_size);
+    let metadata_vaddr = (first_usable.base + hhdm_offset) as *mut u8;
+
+    frames.metadata_start = first_usable.base;
+    frames.metadata_size = metadata_size;
+    frames.max_pfn = max_pfn;
+    frames.hhdm_offset = hhdm_offset;
+
     unsafe {
-        if byte_idx >= BITMAP_SIZE {
-            return false;
-        }
-        let byte = BITMAP_ADDR.add(byte_idx).read();
-        ((byte >> bit_idx) & 1) == 0
+        frames.allocated = Bitmap::new(metadata_vaddr, bitmap_size);
+        frames.usable = Bitmap::new(metadata_vaddr.add(bitmap_size), bitmap_size);
+        frames.poisoned = Bitmap::new(metadata_vaddr.add(2 * bitmap_size), bitmap_size);
+        frames.buddy = BuddyAllocator::new(metadata_vaddr.add(3 * bitmap_size), max_pfn, hhdm_offset);
     }
+
+    frames.add_regions(regions, RegionType::Usable);
 }
 
-fn mark_frame_free(pfn: usize) {
-    let (byte_idx, bit_idx) = frame_to_byte_bit(pfn);
-    unsafe {
-        if byte_idx >= BITMAP_SIZE {
-            return;
-        }
+/// Hand the frames of every `kind` region to the allocator once their contents are no
+/// longer needed. Returns how many frames were added.
+pub fn reclaim(regions: &[MemoryRegion], kind: RegionType) -> usize {
+    FRAMES.lock().add_regions(regions, kind)
+}
 
-        let byte = BITMAP_ADDR.add(byte_idx).read();
-        let new_byte = byte & !(1 << bit_idx);
-        BITMAP_ADDR.add(byte_idx).write(new_byte);
+/// Record a `FrameFault` event for the misused frame, caused by the current context,
+/// then panic. FRAMES must not be held.
+fn fault(Fault { pfn, reason }: Fault) -> ! {
+    let phys = (pfn * PAGE_SIZE) as u64;
+    if causality::is_initialized() {
+        context::emit(EventKind::FrameFault, RootCause::Misuse, EventData::FrameFault { phys, reason: reason as u8 });
     }
+    panic!("Frame allocator fault at {:#x}: {:?}", phys, reason);
 }
 
-fn mark_frame_allocated(pfn: usize) {
-    let (byte_idx, bit_idx) = frame_to_byte_bit(pfn);
-    unsafe {
-        if byte_idx >= BITMAP_SIZE {
-            return;
+/// A single frame.
+pub fn alloc() -> Option<u64> {
+    alloc_order(0)
+}
+
+/// 2^order physically contiguous frames, aligned to their combined size; with
+/// HUGE_PAGE_ORDER or GIANT_PAGE_ORDER, the backing of one huge page.
+pub fn alloc_order(order: usize) -> Option<u64> {
+    let allocated = FRAMES.lock().alloc(order);
+    allocated.unwrap_or_else(|misuse| fault(misuse))
+}
+
+/// `count` physically contiguous frames, for DMA buffers. Frames past `count` in the
+/// underlying block go straight back to the allocator.
+pub fn alloc_contiguous(count: usize) -> Option<u64> {
+    if count == 0 {
+        return None;
+    }
+    let order = count.next_power_of_two().trailing_zeros() as usize;
+    let mut frames = FRAMES.lock();
+    let allocated = match frames.alloc(order) {
+        Ok(Some(base)) => {
+            let tail = base + (count * PAGE_SIZE) as u64;
+            free_run(&mut frames, tail, (1 << order) - count).map(|()| Some(base))
         }
+        other => other,
+    };
+    drop(frames);
+
+    allocated.unwrap_or_else(|misuse| fault(misuse))
+}
 
-        let byte = BITMAP_ADDR.add(byte_idx).read();
-        let new_byte = byte | (1 << bit_idx);
-        BITMAP_ADDR.add(byte_idx).write(new_byte);
+pub fn free(frame_addr: u64) {
+    free_order(frame_addr, 0);
+}
+
+/// Free a block obtained from alloc_order() with the same order.
+pub fn free_order(frame_addr: u64, order: usize) {
+    let freed = FRAMES.lock().free(frame_addr, order);
+    if let Err(misuse) = freed {
+        fault(misuse);
     }
 }
 
-fn max_pfn(regions: &[MemoryRegion]) -> usize {
-    let mut max = 0;
-    for region in regions {
-        let end_addr = region.base + region.length - 1;
-        let end_pfn = (end_addr as usize) / PAGE_SIZE;
-        if end_pfn > max {
-            max = end_pfn
+/// Free frames obtained from alloc_contiguous() with the same count.
+pub fn free_contiguous(frame_addr: u64, count: usize) {
+    let freed = free_run(&mut FRAMES.lock(), frame_addr, count);
+    if let Err(misuse) = freed {
+        fault(misuse);
+    }
+}
+
+pub fn free_frames() -> usize {
+    FRAMES.lock().buddy.free_frames()
+}
+
+/// Free `count` frames from `frame_addr` as the largest aligned blocks that fit.
+fn free_run(frames: &mut FrameAllocator, frame_addr: u64, count: usize) -> Result<(), Fault> {
+    let mut pfn = frame_addr as usize / PAGE_SIZE;
+    let end_pfn = pfn + count;
+
+    while pfn < end_pfn {
+        let mut order = (pfn.trailing_zeros() as usize).min(MAX_ORDER);
+        while pfn + (1 << order) > end_pfn {
+            order -= 1;
         }
+        frames.free((pfn * PAGE_SIZE) as u64, order)?;
+        pfn += 1 << order;
     }
-    max + 1
+    Ok(())
 }
 
-fn start_frame(region: &MemoryRegion) -> usize {
-    (region.base as usize) / PAGE_SIZE
+fn first_usable_region(regions: &[MemoryRegion], size: usize) -> &MemoryRegion {
+    regions
+        .iter()
+        .find(|r| matches!(r.kind, RegionType::Usable) && r.length as usize >= size)
+        .expect("Bootloader should provide at least one usable region")
+}
+
+fn frame_to_byte_bit(pfn: usize) -> (usize, usize) {
+    let byte_idx = pfn / 8;
+    let bit_idx = pfn % 8;
+    (byte_idx, bit_idx)
+}
+
+/// One past the highest frame the allocator can ever manage: the end of the last usable
+/// or reclaimable region. Reserved ranges and MMIO holes above it get no metadata.
+fn max_pfn(regions: &[MemoryRegion]) -> usize {
+    regions
+        .iter()
+        .filter(|region| {
+            matches!(region.kind, RegionType::Usable | RegionType::Bootloader | RegionType::AcpiReclaimable)
+        })
+        .map(|region| whole_frames(region).end)
+        .max()
+        .unwrap_or(0)
 }
 
-fn end_frame(region: &MemoryRegion) -> usize {
-    ((region.base + region.length - 1) as usize) / PAGE_SIZE
+/// Frames lying wholly inside the region. Only usable regions are page-trimmed by the
+/// memory map; a partial page at either end of any other may be shared with firmware.
+fn whole_frames(region: &MemoryRegion) -> Range<usize> {
+    let start = region.base.div_ceil(PAGE_SIZE as u64) as usize;
+    let end = ((region.base + region.length) / PAGE_SIZE as u64) as usize;
+    start..end.max(start)
 }
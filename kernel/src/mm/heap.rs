@@ -0,0 +1,221 @@
+//! Kernel heap backing `alloc` for non-hot kernel code.
+//!
+//! The heap is a virtual region starting at HEAP_BASE that grows on demand: when no free
+//! block fits a request, more frames are taken from the frame allocator and mapped at the
+//! end of the region. Free memory is kept in an address-ordered list of blocks that are
+//! merged with their neighbours on free.
+//!
+//! Requests of up to slab::MAX_OBJECT_SIZE are served by the slab size classes instead,
+//! once the calling CPU has its per-CPU block for the slab magazines. The heap region
+//! takes larger ones, early ones, and any the slabs cannot serve.
+//!
+//! The causality recording path never allocates; it must stay usable from interrupt
+//! handlers and the panic path, where taking the heap lock could deadlock.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr::{NonNull, null_mut};
+
+use crate::arch::x86_64::cpu;
+use crate::sync::SpinLock;
+
+use super::page::{self, PageFlags};
+use super::{frame, slab};
+
+const HEAP_BASE: u64 = 0xffff_c000_0000_0000;
+const HEAP_MAX_SIZE: u64 = 1 << 30;
+const PAGE_SIZE: u64 = 4096;
+/// Minimum growth, to avoid mapping a page at a time for small allocations.
+const GROW_MIN_PAGES: u64 = 16;
+
+/// Every block, free or allocated, is a multiple of this and at least this large, so a
+/// freed block can always hold its free-list node.
+const BLOCK_ALIGN: usize = size_of::<FreeBlock>();
+
+#[global_allocator]
+static HEAP: KernelHeap = KernelHeap {
+    inner: SpinLock::new(Heap::new()),
+};
+
+#[derive(Debug)]
+pub enum HeapError {
+    OutOfFrames,
+    MapFailed,
+    Exhausted,
+}
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct Heap {
+    /// Lowest-addressed free block.
+    free: *mut FreeBlock,
+    /// End of the mapped part of the region.
+    end: u64,
+    hhdm_offset: u64,
+}
+
+unsafe impl Send for Heap {}
+
+struct KernelHeap {
+    inner: SpinLock<Heap>,
+}
+
+impl Heap {
+    const fn new() -> Self {
+        Self {
+            free: null_mut(),
+            end: HEAP_BASE,
+            hhdm_offset: 0,
+        }
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = block_layout(layout);
+
+        if let Some(ptr) = self.take_fit(size, align) {
+            return ptr;
+        }
+        match self.grow(size + align) {
+            Ok(()) => self.take_fit(size, align).unwrap_or(null_mut()),
+            Err(_) => null_mut(),
+        }
+    }
+
+    /// First-fit: carve `size` bytes aligned to `align` out of a free block. Leftovers on
+    /// either side go back on the list; a block whose leftover would be too small to
+    /// hold a node is skipped.
+    fn take_fit(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut current = self.free;
+
+        while !current.is_null() {
+            let block_start = current as usize;
+            let block_end = block_start + unsafe { (*current).size };
+            let next = unsafe { (*current).next };
+
+            let mut start = block_start.next_multiple_of(align);
+            if start != block_start && start - block_start < BLOCK_ALIGN {
+                start = (block_start + BLOCK_ALIGN).next_multiple_of(align);
+            }
+            let end = start + size;
+            let tail = block_end.saturating_sub(end);
+
+            if end <= block_end && (tail == 0 || tail >= BLOCK_ALIGN) {
+                // Unlink, then return the unused head and tail.
+                match prev.is_null() {
+                    true => self.free = next,
+                    false => unsafe { (*prev).next = next },
+                }
+                if start > block_start {
+                    self.insert(block_start, start - block_start);
+                }
+                if tail > 0 {
+                    self.insert(end, tail);
+                }
+                return Some(start as *mut u8);
+            }
+
+            prev = current;
+            current = next;
+        }
+
+        None
+    }
+
+    /// Put `addr..addr + size` back on the list, merging with adjacent free blocks.
+    fn insert(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut next = self.free;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = unsafe { (*next).next };
+        }
+
+        let block = addr as *mut FreeBlock;
+        unsafe {
+            block.write(FreeBlock { size, next });
+
+            if !next.is_null() && addr + size == next as usize {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
+
+            if prev.is_null() {
+                self.free = block;
+            } else if prev as usize + (*prev).size == addr {
+                (*prev).size += (*block).size;
+                (*prev).next = (*block).next;
+            } else {
+                (*prev).next = block;
+            }
+        }
+    }
+
+    /// Map at least `bytes` more of the region and add it to the free list. On failure,
+    /// the pages mapped before it are still added.
+    fn grow(&mut self, bytes: usize) -> Result<(), HeapError> {
+        let pages = (bytes as u64).div_ceil(PAGE_SIZE).max(GROW_MIN_PAGES);
+        if self.end + pages * PAGE_SIZE > HEAP_BASE + HEAP_MAX_SIZE {
+            return Err(HeapError::Exhausted);
+        }
+
+        let start = self.end;
+        let mapped = self.map_pages(pages);
+        if self.end > start {
+            self.insert(start as usize, (self.end - start) as usize);
+        }
+        mapped
+    }
+
+    /// Back `pages` pages at the end of the region with fresh frames, advancing `end`
+    /// past each page as it is mapped.
+    fn map_pages(&mut self, pages: u64) -> Result<(), HeapError> {
+        for _ in 0..pages {
+            let phys_addr = frame::alloc().ok_or(HeapError::OutOfFrames)?;
+            if page::map(self.end, phys_addr, PageFlags::WRITABLE, self.hhdm_offset).is_err() {
+                frame::free(phys_addr);
+                return Err(HeapError::MapFailed);
+            }
+            self.end += PAGE_SIZE;
+        }
+        Ok(())
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size().max(layout.align()) <= slab::MAX_OBJECT_SIZE
+            && cpu::is_initialized()
+            && let Ok(object) = slab::alloc(layout)
+        {
+            return object.as_ptr();
+        }
+        self.inner.lock().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if slab::owns(ptr) {
+            slab::free(unsafe { NonNull::new_unchecked(ptr) });
+            return;
+        }
+        let (size, _) = block_layout(layout);
+        self.inner.lock().insert(ptr as usize, size);
+    }
+}
+
+/// Size and alignment of the block backing `layout`.
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let size = layout.size().max(BLOCK_ALIGN).next_multiple_of(BLOCK_ALIGN);
+    let align = layout.align().max(align_of::<FreeBlock>());
+    (size, align)
+}
+
+/// Map the initial heap. Must run after the frame allocator is initialized.
+pub fn init(hhdm_offset: u64) -> Result<(), HeapError> {
+    let mut heap = HEAP.inner.lock();
+    heap.hhdm_offset = hhdm_offset;
+    heap.grow(0)
+}
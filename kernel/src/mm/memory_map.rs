@@ -0,0 +1,138 @@
+//! Normalization of the firmware memory map into the kernel's own copy.
+//!
+//! The raw map may be unsorted, overlap itself and report usable regions that start or
+//! end mid-page. build() turns it into a sorted list of disjoint regions, where an
+//! overlap goes to the most restrictive type and adjacent regions of one type are
+//! merged. The list is sized from the raw entry count and stored in pages taken from
+//! the start of the largest usable region, since no allocator exists yet; those pages
+//! are reported as `RegionType::Kernel`.
+
+use core::mem::size_of;
+use core::slice;
+
+use crate::println;
+
+use super::types::{MemoryRegion, RegionType};
+
+const PAGE_SIZE: u64 = 4096;
+
+const REGION_TYPES: [RegionType; 6] = [
+    RegionType::Usable,
+    RegionType::Bootloader,
+    RegionType::AcpiReclaimable,
+    RegionType::Kernel,
+    RegionType::Reserved,
+    RegionType::Unknown,
+];
+
+/// Build the normalized map from the `raw` regions, which may be iterated more than once.
+pub fn build<I>(raw: I, hhdm_offset: u64) -> &'static [MemoryRegion]
+where
+    I: Iterator<Item = MemoryRegion> + Clone,
+{
+    // Every input contributes two boundaries; the carved storage is one more input.
+    let inputs = raw.clone().count() + 1;
+    let boundaries_size = 2 * inputs * size_of::<u64>();
+    let regions_size = 2 * inputs * size_of::<MemoryRegion>();
+    let storage_size = (boundaries_size + regions_size) as u64;
+
+    let largest = raw
+        .clone()
+        .filter_map(trim)
+        .filter(|region| region.kind == RegionType::Usable)
+        .max_by_key(|region| region.length)
+        .filter(|region| region.length >= storage_size)
+        .expect("Bootloader should provide a usable region large enough for the memory map");
+    let storage = MemoryRegion {
+        base: largest.base,
+        length: storage_size.next_multiple_of(PAGE_SIZE),
+        kind: RegionType::Kernel,
+    };
+
+    let storage_addr = (storage.base + hhdm_offset) as *mut u8;
+    let boundaries = unsafe { slice::from_raw_parts_mut(storage_addr as *mut u64, 2 * inputs) };
+    let regions = unsafe {
+        slice::from_raw_parts_mut(storage_addr.add(boundaries_size) as *mut MemoryRegion, 2 * inputs)
+    };
+
+    let inputs = || raw.clone().filter_map(trim).chain(Some(storage));
+
+    let mut boundary_count = 0;
+    for region in inputs() {
+        boundaries[boundary_count] = region.base;
+        boundaries[boundary_count + 1] = region.base + region.length;
+        boundary_count += 2;
+    }
+    let boundaries = &mut boundaries[..boundary_count];
+    boundaries.sort_unstable();
+
+    // Each span between consecutive boundaries gets the most restrictive type covering
+    // it, and is merged into the previous region when that has the same type and ends
+    // where the span starts.
+    let mut count = 0;
+    for span in boundaries.windows(2) {
+        let (start, end) = (span[0], span[1]);
+        if start == end {
+            continue;
+        }
+
+        let Some(kind) = inputs()
+            .filter(|region| region.base <= start && end <= region.base + region.length)
+            .map(|region| region.kind)
+            .max_by_key(|&kind| restriction(kind))
+        else {
+            continue;
+        };
+
+        if count > 0 {
+            let last = &mut regions[count - 1];
+            if last.kind == kind && last.base + last.length == start {
+                last.length += end - start;
+                continue;
+            }
+        }
+        regions[count] = MemoryRegion { base: start, length: end - start, kind };
+        count += 1;
+    }
+
+    &regions[..count]
+}
+
+/// Print how many regions and bytes the map has of each type.
+pub fn print_summary(regions: &[MemoryRegion]) {
+    println!("{:<18} {:>7} {:>12}", "type", "regions", "KiB");
+    for kind in REGION_TYPES {
+        let (count, bytes) = regions
+            .iter()
+            .filter(|region| region.kind == kind)
+            .fold((0, 0), |(count, bytes), region| (count + 1, bytes + region.length));
+        if count > 0 {
+            let (name, kib) = (kind.name(), bytes / 1024);
+            println!("{:<18} {:>7} {:>12}", name, count, kib);
+        }
+    }
+}
+
+/// Shrink usable regions to whole pages, dropping ones with none. Other regions are
+/// kept as reported.
+fn trim(region: MemoryRegion) -> Option<MemoryRegion> {
+    if region.kind != RegionType::Usable {
+        return (region.length > 0).then_some(region);
+    }
+
+    let base = region.base.next_multiple_of(PAGE_SIZE);
+    let end = (region.base + region.length) / PAGE_SIZE * PAGE_SIZE;
+    (end > base).then_some(MemoryRegion { base, length: end - base, kind: region.kind })
+}
+
+/// Which type wins where regions overlap: the higher, the less the kernel may do with it.
+fn restriction(kind: RegionType) -> u8 {
+    match kind {
+        RegionType::Usable => 0,
+        RegionType::Bootloader => 1,
+        RegionType::AcpiReclaimable => 2,
+        RegionType::Kernel => 3,
+        RegionType::Reserved => 4,
+        RegionType::Unknown => 5,
+    }
+}
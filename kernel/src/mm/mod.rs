@@ -1,4 +1,10 @@
+pub mod address_space;
+pub mod buddy;
 pub mod frame;
+pub mod heap;
+pub mod memory_map;
 pub mod page;
+pub mod reclaim;
+pub mod slab;
 pub mod stack;
 pub mod types;
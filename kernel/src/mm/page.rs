@@ -1,7 +1,55 @@
 use core::ptr::write_bytes;
+use core::sync::atomic::{AtomicU64, Ordering};
 
-use crate::arch::x86_64::mmu;
+use bitflags::bitflags;
+
+use crate::arch::x86_64::{cpu, mmu};
 use crate::mm::frame;
+use crate::sync::SpinLock;
+
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Physical address of the PML4 this module edits once replace_root() has run; until
+/// then, the one in CR3.
+static ROOT: AtomicU64 = AtomicU64::new(0);
+
+/// Held for every walk and edit of the page tables, so cores cannot race to allocate the
+/// same table or lose each other's entries. Taken before the frame allocator's lock.
+///
+/// There is no TLB shootdown yet: unmap, protect and huge-page splits only invalidate the
+/// calling CPU's TLB, and other CPUs may keep stale translations until CR3 is reloaded.
+/// This is sound only while APs run nothing that touches changed mappings, as today,
+/// where they halt once started.
+static TABLES: SpinLock<PageTables> = SpinLock::new(PageTables);
+
+/// Stands for the page tables under TABLES; every walk and edit is a method on it.
+struct PageTables;
+
+bitflags! {
+    /// Page table entry flags. The address bits are not flags and never appear here.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct PageFlags: u64 {
+        const PRESENT = 1 << 0;
+        const WRITABLE = 1 << 1;
+        const USER = 1 << 2;
+        /// PWT
+        const WRITE_THROUGH = 1 << 3;
+        /// PCD
+        const CACHE_DISABLE = 1 << 4;
+        const ACCESSED = 1 << 5;
+        const DIRTY = 1 << 6;
+        /// PS: the entry maps a 2 MiB or 1 GiB page instead of pointing to a table.
+        const HUGE = 1 << 7;
+        /// PAT in a 4 KiB entry; the same bit as HUGE in directory entries.
+        const PAT = 1 << 7;
+        /// PAT in a 2 MiB or 1 GiB entry.
+        const HUGE_PAT = 1 << 12;
+        const GLOBAL = 1 << 8;
+        /// Software bit: the page is a guard page and must never be mapped.
+        const GUARD = 1 << 9;
+        const NO_EXECUTE = 1 << 63;
+    }
+}
 
 #[derive(Clone, Copy)]
 pub struct PageTableEntry {
@@ -9,30 +57,42 @@ pub struct PageTableEntry {
 }
 
 impl PageTableEntry {
-    pub const PRESENT: u64 = 1 << 0;
-    pub const WRITABLE: u64 = 1 << 1;
-    pub const HUGE: u64 = 1 << 7;
-    pub const GUARD: u64 = 1 << 9;
-    const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
-    
-    pub fn new(value: u64) -> Self {
-        Self { entry: value }
+    const EMPTY: Self = Self { entry: 0 };
+
+    pub fn new(phys_addr: u64, flags: PageFlags) -> Self {
+        Self {
+            entry: (phys_addr & ADDR_MASK) | flags.bits(),
+        }
+    }
+
+    pub fn flags(&self) -> PageFlags {
+        PageFlags::from_bits_retain(self.entry & !ADDR_MASK)
+    }
+
+    /// Flags of a 2 MiB or 1 GiB entry, where bit 12 is HUGE_PAT rather than an address
+    /// bit.
+    pub fn huge_flags(&self) -> PageFlags {
+        self.flags() | PageFlags::from_bits_retain(self.entry & PageFlags::HUGE_PAT.bits())
     }
 
     pub fn is_present(&self) -> bool {
-        (self.entry & Self::PRESENT) != 0
+        self.flags().contains(PageFlags::PRESENT)
     }
 
     pub fn is_huge(&self) -> bool {
-        (self.entry & Self::HUGE) != 0
+        self.flags().contains(PageFlags::HUGE)
     }
 
     pub fn is_guard(&self) -> bool {
-        (self.entry & Self::GUARD) != 0
+        self.flags().contains(PageFlags::GUARD)
     }
 
     pub fn addr(&self) -> u64 {
-        self.entry & Self::ADDR_MASK
+        self.entry & ADDR_MASK
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entry == 0
     }
 }
 
@@ -51,8 +111,65 @@ impl PageTable {
         let idx = ((virt_addr >> bit_shift) & 0x1FF) as usize;
         self.entries[idx] = entry
     }
+
+    fn is_empty(&self) -> bool {
+        self.entries.iter().all(PageTableEntry::is_empty)
+    }
+}
+
+/// Size of the page a leaf entry maps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl PageSize {
+    pub const fn bytes(self) -> u64 {
+        match self {
+            PageSize::Size4KiB => 1 << 12,
+            PageSize::Size2MiB => 1 << 21,
+            PageSize::Size1GiB => 1 << 30,
+        }
+    }
+
+    /// Frame allocator order of a block backing one page of this size.
+    pub const fn order(self) -> usize {
+        match self {
+            PageSize::Size4KiB => 0,
+            PageSize::Size2MiB => frame::HUGE_PAGE_ORDER,
+            PageSize::Size1GiB => frame::GIANT_PAGE_ORDER,
+        }
+    }
+
+    /// 1 GiB pages need the pdpe1gb CPU feature; the others always work.
+    pub fn supported(self) -> bool {
+        self != PageSize::Size1GiB || cpu::has_1gib_pages()
+    }
+
+    /// Shift of the index selecting the entry that maps a page of this size.
+    const fn shift(self) -> u32 {
+        match self {
+            PageSize::Size4KiB => 12,
+            PageSize::Size2MiB => 21,
+            PageSize::Size1GiB => 30,
+        }
+    }
+
+    /// Level of the table holding the entry that maps a page of this size; the PML4 is 0.
+    const fn level(self) -> usize {
+        match self {
+            PageSize::Size4KiB => 3,
+            PageSize::Size2MiB => 2,
+            PageSize::Size1GiB => 1,
+        }
+    }
 }
 
+/// Index shift of the entries in the PML4, PDPT and PD.
+const TABLE_SHIFTS: [u32; 3] = [39, 30, 21];
+
 #[derive(Debug)]
 enum PteError {
     HugePage,
@@ -63,86 +180,452 @@ enum PteError {
 #[derive(Debug)]
 pub enum MapError {
     AlreadyMapped,
-    HugePage,
     OutOfMemory,
     GuardPage,
+    /// The addresses are not aligned to the page size.
+    Misaligned,
+    /// The CPU has no 1 GiB pages.
+    Unsupported,
 }
 
 #[derive(Debug)]
 pub enum UnmapError {
-    HugePage,
     NotMapped,
     GuardPage,
+    /// No frame was left to split a huge page with.
+    OutOfMemory,
 }
 
-pub fn map(virt_addr: u64, phys_addr: u64, flags: u64, hhdm_offset: u64) -> Result<(), MapError> {
-    let pte = match get_pte_mut(virt_addr, hhdm_offset, true) {
-        Ok(pte) => pte,
-        Err(PteError::HugePage) => return Err(MapError::HugePage),
-        Err(PteError::OutOfMemory) => return Err(MapError::OutOfMemory),
-        Err(PteError::NotMapped) => unreachable!("allocate=true guarantees mapping"),
-    };
+#[derive(Debug)]
+pub enum ProtectError {
+    NotMapped,
+    GuardPage,
+    /// No frame was left to split a huge page with.
+    OutOfMemory,
+}
 
-    if pte.is_present() { return Err(MapError::AlreadyMapped); }
-    if pte.is_guard() { return Err(MapError::GuardPage); }
-    *pte = PageTableEntry::new(phys_addr | flags);
-    Ok(())
+/// Map the 4 KiB page at `virt_addr` to `phys_addr`. PRESENT is implied.
+pub fn map(
+    virt_addr: u64,
+    phys_addr: u64,
+    flags: PageFlags,
+    hhdm_offset: u64,
+) -> Result<(), MapError> {
+    TABLES
+        .lock()
+        .map_page(virt_addr, phys_addr, PageSize::Size4KiB, flags, hhdm_offset)
 }
 
-pub fn unmap(virt_addr: u64, hhdm_offset: u64) -> Result<u64, UnmapError> {
-    let pte = match get_pte_mut(virt_addr, hhdm_offset, false) {
-        Ok(pte) => pte,
-        Err(PteError::HugePage) => return Err(UnmapError::HugePage),
-        Err(PteError::NotMapped) => return Err(UnmapError::NotMapped),
-        Err(PteError::OutOfMemory) => unreachable!("allocate=false guarantees no new mapping"),
-    };
+/// Map one page of `size` at `virt_addr` to `phys_addr`, both aligned to it. `flags`
+/// use the 4 KiB layout for every size: PAT is moved to HUGE_PAT in huge entries, and
+/// HUGE and PRESENT are implied.
+pub fn map_page(
+    virt_addr: u64,
+    phys_addr: u64,
+    size: PageSize,
+    flags: PageFlags,
+    hhdm_offset: u64,
+) -> Result<(), MapError> {
+    TABLES
+        .lock()
+        .map_page(virt_addr, phys_addr, size, flags, hhdm_offset)
+}
 
-    if pte.is_guard() { return Err(UnmapError::GuardPage); }
-    if !pte.is_present() { return Err(UnmapError::NotMapped); }
-    let phys_addr = pte.addr();
-    *pte = PageTableEntry::new(0);
-    mmu::invalidate_page(virt_addr);
-    Ok(phys_addr)
+/// Unmap the 4 KiB page at `virt_addr` and return the frame it mapped. A huge page
+/// covering it is split first. Page tables left empty are freed, up to but excluding
+/// the PML4.
+pub fn unmap(virt_addr: u64, hhdm_offset: u64) -> Result<u64, UnmapError> {
+    TABLES
+        .lock()
+        .unmap_page(virt_addr, PageSize::Size4KiB, hhdm_offset)
 }
 
 pub fn map_guard(virt_addr: u64, hhdm_offset: u64) -> Result<(), MapError> {
-    let pte = match get_pte_mut(virt_addr, hhdm_offset, true) {
-        Ok(pte) => pte,
-        Err(PteError::HugePage) => return Err(MapError::HugePage),
-        Err(PteError::OutOfMemory) => return Err(MapError::OutOfMemory),
-        Err(PteError::NotMapped) => unreachable!("allocate=true guarantees mapping"),
-    };
+    TABLES.lock().map_guard(virt_addr, hhdm_offset)
+}
 
-    if pte.is_present() { return Err(MapError::AlreadyMapped); }
-    if pte.is_guard() { return Err(MapError::GuardPage); }
+/// Map `size` bytes at `virt_addr` to the physical range at `phys_addr`, using the
+/// largest pages both addresses are aligned to. On failure the pages mapped so far are
+/// unmapped again.
+pub fn map_range(
+    virt_addr: u64,
+    phys_addr: u64,
+    size: u64,
+    flags: PageFlags,
+    hhdm_offset: u64,
+) -> Result<(), MapError> {
+    let mut tables = TABLES.lock();
 
-    *pte = PageTableEntry::new(PageTableEntry::GUARD);
+    let mut offset = 0;
+    while offset < size {
+        let (virt, phys) = (virt_addr + offset, phys_addr + offset);
+        let page = [PageSize::Size1GiB, PageSize::Size2MiB]
+            .into_iter()
+            .find(|&page| {
+                page.supported()
+                    && (virt | phys).is_multiple_of(page.bytes())
+                    && size - offset >= page.bytes()
+            })
+            .unwrap_or(PageSize::Size4KiB);
+
+        if let Err(err) = tables.map_page(virt, phys, page, flags, hhdm_offset) {
+            let _ = tables.unmap_range(virt_addr, offset, hhdm_offset);
+            return Err(err);
+        }
+        offset += page.bytes();
+    }
     Ok(())
 }
 
+/// Unmap `size` bytes at `virt_addr`. The frames are not freed. Huge pages only partly
+/// in the range are split. Stops at the first page that cannot be unmapped.
+pub fn unmap_range(virt_addr: u64, size: u64, hhdm_offset: u64) -> Result<(), UnmapError> {
+    TABLES.lock().unmap_range(virt_addr, size, hhdm_offset)
+}
+
+/// Replace the flags of every page in `size` bytes at `virt_addr`, keeping what they
+/// map. `flags` use the 4 KiB layout and PRESENT is implied. Huge pages only partly in
+/// the range are split. Stops at the first page that is not mapped.
+pub fn protect_range(
+    virt_addr: u64,
+    size: u64,
+    flags: PageFlags,
+    hhdm_offset: u64,
+) -> Result<(), ProtectError> {
+    TABLES
+        .lock()
+        .protect_range(virt_addr, size, flags, hhdm_offset)
+}
+
+/// Physical address `virt_addr` maps to and the flags of the entry mapping it,
+/// following 1 GiB and 2 MiB pages.
+pub fn translate(virt_addr: u64, hhdm_offset: u64) -> Option<(u64, PageFlags)> {
+    TABLES.lock().translate(virt_addr, hhdm_offset)
+}
+
+/// Physical address of the PML4 every function of this module edits.
+pub fn root() -> u64 {
+    match ROOT.load(Ordering::Relaxed) {
+        0 => mmu::read_cr3() & ADDR_MASK,
+        root => root,
+    }
+}
+
+/// Make a new, empty PML4 the one this module edits, and return its physical address.
+/// A CPU only uses it once it is loaded into CR3.
+pub fn replace_root(hhdm_offset: u64) -> Result<u64, MapError> {
+    let _tables = TABLES.lock();
+    let pml4 = allocate_table(hhdm_offset).map_err(|_| MapError::OutOfMemory)?;
+    ROOT.store(pml4, Ordering::Relaxed);
+    Ok(pml4)
+}
+
+impl PageTables {
+    fn map_page(
+        &mut self,
+        virt_addr: u64,
+        phys_addr: u64,
+        size: PageSize,
+        flags: PageFlags,
+        hhdm_offset: u64,
+    ) -> Result<(), MapError> {
+        if !size.supported() {
+            return Err(MapError::Unsupported);
+        }
+        if !(virt_addr | phys_addr).is_multiple_of(size.bytes()) {
+            return Err(MapError::Misaligned);
+        }
+
+        let pte = match self.leaf_mut(virt_addr, size, hhdm_offset, true, false) {
+            Ok((_, pte)) => pte,
+            Err(PteError::HugePage) => return Err(MapError::AlreadyMapped),
+            Err(PteError::OutOfMemory) => return Err(MapError::OutOfMemory),
+            Err(PteError::NotMapped) => unreachable!("allocate=true guarantees mapping"),
+        };
+
+        // A present entry is either a page or a table with pages below it.
+        if pte.is_present() {
+            return Err(MapError::AlreadyMapped);
+        }
+        if pte.is_guard() {
+            return Err(MapError::GuardPage);
+        }
+        *pte = PageTableEntry::new(phys_addr, leaf_flags(flags | PageFlags::PRESENT, size));
+        Ok(())
+    }
+
+    fn map_guard(&mut self, virt_addr: u64, hhdm_offset: u64) -> Result<(), MapError> {
+        let pte = match self.leaf_mut(virt_addr, PageSize::Size4KiB, hhdm_offset, true, false) {
+            Ok((_, pte)) => pte,
+            Err(PteError::HugePage) => return Err(MapError::AlreadyMapped),
+            Err(PteError::OutOfMemory) => return Err(MapError::OutOfMemory),
+            Err(PteError::NotMapped) => unreachable!("allocate=true guarantees mapping"),
+        };
+
+        if pte.is_present() {
+            return Err(MapError::AlreadyMapped);
+        }
+        if pte.is_guard() {
+            return Err(MapError::GuardPage);
+        }
+
+        *pte = PageTableEntry::new(0, PageFlags::GUARD);
+        Ok(())
+    }
+
+    fn unmap_range(
+        &mut self,
+        virt_addr: u64,
+        size: u64,
+        hhdm_offset: u64,
+    ) -> Result<(), UnmapError> {
+        let mut offset = 0;
+        while offset < size {
+            let page = self.covering_size(virt_addr + offset, size - offset, hhdm_offset);
+            self.unmap_page(virt_addr + offset, page, hhdm_offset)?;
+            offset += page.bytes();
+        }
+        Ok(())
+    }
+
+    fn unmap_page(
+        &mut self,
+        virt_addr: u64,
+        size: PageSize,
+        hhdm_offset: u64,
+    ) -> Result<u64, UnmapError> {
+        let (tables, pte) = match self.leaf_mut(virt_addr, size, hhdm_offset, false, true) {
+            Ok(leaf) => leaf,
+            Err(PteError::NotMapped) => return Err(UnmapError::NotMapped),
+            Err(PteError::OutOfMemory) => return Err(UnmapError::OutOfMemory),
+            Err(PteError::HugePage) => unreachable!("split=true splits huge pages"),
+        };
+
+        if pte.is_guard() {
+            return Err(UnmapError::GuardPage);
+        }
+        if !pte.is_present() {
+            return Err(UnmapError::NotMapped);
+        }
+        let phys_addr = pte.addr() & !(size.bytes() - 1);
+        *pte = PageTableEntry::EMPTY;
+        mmu::invalidate_page(virt_addr);
+
+        self.free_empty_tables(&tables[..=size.level()], virt_addr, hhdm_offset);
+        Ok(phys_addr)
+    }
+
+    fn protect_range(
+        &mut self,
+        virt_addr: u64,
+        size: u64,
+        flags: PageFlags,
+        hhdm_offset: u64,
+    ) -> Result<(), ProtectError> {
+        let mut offset = 0;
+        while offset < size {
+            let virt = virt_addr + offset;
+            let page = self.covering_size(virt, size - offset, hhdm_offset);
+            let pte = match self.leaf_mut(virt, page, hhdm_offset, false, true) {
+                Ok((_, pte)) => pte,
+                Err(PteError::NotMapped) => return Err(ProtectError::NotMapped),
+                Err(PteError::OutOfMemory) => return Err(ProtectError::OutOfMemory),
+                Err(PteError::HugePage) => unreachable!("split=true splits huge pages"),
+            };
+
+            if pte.is_guard() {
+                return Err(ProtectError::GuardPage);
+            }
+            if !pte.is_present() {
+                return Err(ProtectError::NotMapped);
+            }
+            let phys_addr = pte.addr() & !(page.bytes() - 1);
+            *pte = PageTableEntry::new(phys_addr, leaf_flags(flags | PageFlags::PRESENT, page));
+            mmu::invalidate_page(virt);
+            offset += page.bytes();
+        }
+        Ok(())
+    }
+
+    fn translate(&self, virt_addr: u64, hhdm_offset: u64) -> Option<(u64, PageFlags)> {
+        let mut table = unsafe { table_at(root(), hhdm_offset) };
+
+        for bit_shift in [39, 30, 21, 12] {
+            let entry = *table.get_entry(virt_addr, bit_shift);
+            if !entry.is_present() {
+                return None;
+            }
+            if bit_shift == 12 || (bit_shift != 39 && entry.is_huge()) {
+                let offset_mask = (1u64 << bit_shift) - 1;
+                // Masking the offset bits also drops HUGE_PAT from a huge entry's address.
+                let base = entry.addr() & !offset_mask;
+                let flags = match bit_shift {
+                    12 => entry.flags(),
+                    _ => entry.huge_flags(),
+                };
+                return Some((base | (virt_addr & offset_mask), flags));
+            }
+            table = unsafe { table_at(entry.addr(), hhdm_offset) };
+        }
+
+        None
+    }
+
+    /// Largest page size that fits in `remaining` bytes at `virt_addr`, is aligned there,
+    /// and is no larger than the page currently mapping `virt_addr`.
+    fn covering_size(&self, virt_addr: u64, remaining: u64, hhdm_offset: u64) -> PageSize {
+        let mapped = self.mapped_size(virt_addr, hhdm_offset);
+        [PageSize::Size1GiB, PageSize::Size2MiB]
+            .into_iter()
+            .find(|&page| {
+                page.bytes() <= mapped.bytes()
+                    && virt_addr.is_multiple_of(page.bytes())
+                    && remaining >= page.bytes()
+            })
+            .unwrap_or(PageSize::Size4KiB)
+    }
+
+    /// Size of the huge page mapping `virt_addr`, or 4 KiB when none does.
+    fn mapped_size(&self, virt_addr: u64, hhdm_offset: u64) -> PageSize {
+        let mut table = unsafe { table_at(root(), hhdm_offset) };
+
+        for (bit_shift, size) in [
+            (39, None),
+            (30, Some(PageSize::Size1GiB)),
+            (21, Some(PageSize::Size2MiB)),
+        ] {
+            let entry = *table.get_entry(virt_addr, bit_shift);
+            if !entry.is_present() {
+                break;
+            }
+            if let Some(size) = size
+                && entry.is_huge()
+            {
+                return size;
+            }
+            table = unsafe { table_at(entry.addr(), hhdm_offset) };
+        }
+
+        PageSize::Size4KiB
+    }
+
+    /// The entry mapping `virt_addr` at `size`, and the physical addresses of the tables
+    /// on the path to it, the PML4 first. Missing tables are allocated when `allocate` is
+    /// set, and huge pages in the way are split when `split` is set.
+    fn leaf_mut(
+        &mut self,
+        virt_addr: u64,
+        size: PageSize,
+        hhdm_offset: u64,
+        allocate: bool,
+        split: bool,
+    ) -> Result<([u64; 4], &'static mut PageTableEntry), PteError> {
+        let mut tables = [root(), 0, 0, 0];
+
+        for level in 0..size.level() {
+            let table = unsafe { table_at(tables[level], hhdm_offset) };
+            let bit_shift = TABLE_SHIFTS[level];
+            let entry = *table.get_entry(virt_addr, bit_shift);
+
+            if entry.is_present() && entry.is_huge() {
+                if !split {
+                    return Err(PteError::HugePage);
+                }
+                self.split_huge(table, virt_addr, bit_shift, hhdm_offset)?;
+            } else if !entry.is_present() {
+                if !allocate {
+                    return Err(PteError::NotMapped);
+                }
+                let new_table_phys = allocate_table(hhdm_offset)?;
+                table.set_entry(
+                    virt_addr,
+                    bit_shift,
+                    PageTableEntry::new(new_table_phys, PageFlags::PRESENT | PageFlags::WRITABLE),
+                );
+            }
+            tables[level + 1] = table.get_entry(virt_addr, bit_shift).addr();
+        }
+
+        let table = unsafe { table_at(tables[size.level()], hhdm_offset) };
+        Ok((tables, table.get_entry(virt_addr, size.shift())))
+    }
+
+    /// Replace the huge page entry for `virt_addr` in `table` with a table of 512 entries
+    /// mapping the same memory with the same flags, one size down.
+    fn split_huge(
+        &mut self,
+        table: &mut PageTable,
+        virt_addr: u64,
+        bit_shift: u32,
+        hhdm_offset: u64,
+    ) -> Result<(), PteError> {
+        let entry = *table.get_entry(virt_addr, bit_shift);
+        let child_shift = bit_shift - 9;
+        let base = entry.addr() & !((1 << bit_shift) - 1);
+
+        // 4 KiB entries keep PAT in bit 7, where directory entries have HUGE.
+        let mut flags = entry.huge_flags();
+        if child_shift == 12 {
+            let pat = flags.contains(PageFlags::HUGE_PAT);
+            flags.remove(PageFlags::HUGE | PageFlags::HUGE_PAT);
+            flags.set(PageFlags::PAT, pat);
+        }
+
+        let child_phys = allocate_table(hhdm_offset)?;
+        let child = unsafe { table_at(child_phys, hhdm_offset) };
+        for (i, child_entry) in child.entries.iter_mut().enumerate() {
+            *child_entry = PageTableEntry::new(base + ((i as u64) << child_shift), flags);
+        }
+
+        // Access rights are the intersection of every level, so the new table entry grants
+        // everything and leaves the restrictions to the entries below it.
+        let table_flags = PageFlags::PRESENT | PageFlags::WRITABLE | (flags & PageFlags::USER);
+        table.set_entry(
+            virt_addr,
+            bit_shift,
+            PageTableEntry::new(child_phys, table_flags),
+        );
+        mmu::invalidate_page(virt_addr);
+        Ok(())
+    }
+
+    /// Free the tables at the end of `tables` for as long as each is left empty, clearing
+    /// the entry that pointed to it. The PML4, first, is never freed. Guard entries keep a
+    /// table alive.
+    fn free_empty_tables(&mut self, tables: &[u64], virt_addr: u64, hhdm_offset: u64) {
+        for level in (1..tables.len()).rev() {
+            if !unsafe { table_at(tables[level], hhdm_offset) }.is_empty() {
+                break;
+            }
+            let parent = unsafe { table_at(tables[level - 1], hhdm_offset) };
+            parent.set_entry(virt_addr, TABLE_SHIFTS[level - 1], PageTableEntry::EMPTY);
+            // INVLPG also drops cached paging-structure entries, so the freed table is no
+            // longer walked.
+            mmu::invalidate_page(virt_addr);
+            frame::free(tables[level]);
+        }
+    }
+}
+
 unsafe fn table_at(phys_addr: u64, hhdm_offset: u64) -> &'static mut PageTable {
     unsafe { &mut *((phys_addr + hhdm_offset) as *mut PageTable) }
 }
 
-fn get_pte_mut(virt_addr: u64, hhdm_offset: u64, allocate: bool) -> Result<&'static mut PageTableEntry, PteError> {
-    let pml4 = unsafe { table_at(mmu::read_cr3() & PageTableEntry::ADDR_MASK, hhdm_offset) };
-    
-    let pdp = get_or_allocate_table(pml4, virt_addr, 39, hhdm_offset, allocate)?;
-    if pdp.get_entry(virt_addr, 30).is_huge() { return Err(PteError::HugePage); } 
-    
-    let pd = get_or_allocate_table(pdp, virt_addr, 30, hhdm_offset, allocate)?;
-    if pd.get_entry(virt_addr, 21).is_huge() { return Err(PteError::HugePage); }
-
-    let pt = get_or_allocate_table(pd, virt_addr, 21, hhdm_offset, allocate)?;
-    Ok(pt.get_entry(virt_addr, 12))
+/// Entry flags for a page of `size` described by `flags` in the 4 KiB layout.
+fn leaf_flags(flags: PageFlags, size: PageSize) -> PageFlags {
+    if size == PageSize::Size4KiB {
+        return flags;
+    }
+    let pat = flags.contains(PageFlags::PAT);
+    let mut flags = flags | PageFlags::HUGE;
+    flags.set(PageFlags::HUGE_PAT, pat);
+    flags
 }
 
-fn get_or_allocate_table(page_table: &mut PageTable, virt_addr: u64, bit_shift: u32, hhdm_offset: u64, allocate: bool) -> Result<&'static mut PageTable, PteError> {
-    if !page_table.get_entry(virt_addr, bit_shift).is_present() { 
-        if !allocate { return Err(PteError::NotMapped); }
-        let new_table_phys = frame::alloc().ok_or(PteError::OutOfMemory)?;
-        unsafe { write_bytes((new_table_phys + hhdm_offset) as *mut u8, 0x00, 4096); }
-        page_table.set_entry(virt_addr, bit_shift, PageTableEntry::new(new_table_phys | PageTableEntry::PRESENT | PageTableEntry::WRITABLE));
+fn allocate_table(hhdm_offset: u64) -> Result<u64, PteError> {
+    let table_phys = frame::alloc().ok_or(PteError::OutOfMemory)?;
+    unsafe {
+        write_bytes((table_phys + hhdm_offset) as *mut u8, 0x00, 4096);
     }
-    Ok(unsafe { table_at(page_table.get_entry(virt_addr, bit_shift).addr(), hhdm_offset) })
+    Ok(table_phys)
 }
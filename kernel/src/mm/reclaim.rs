@@ -0,0 +1,42 @@
+//! Staged return of boot-time memory to the frame allocator.
+//!
+//! Bootloader and ACPI-reclaimable regions hold data the kernel reads while booting, so
+//! they are only handed over once every reader is done: bootloader memory after the
+//! responses are copied and the application processors have left their bootloader
+//! stacks, ACPI memory after the tables are parsed. The page tables the bootloader built
+//! go with its memory, as every CPU has switched to the kernel's own by then.
+
+use crate::acpi;
+use crate::boot::limine;
+use crate::causality::context;
+use crate::causality::types::{EventData, EventKind, ReclaimedRegion, RootCause};
+
+use super::types::RegionType;
+use super::frame;
+
+/// Reclaim bootloader memory. Returns how many frames were reclaimed.
+///
+/// Must run after smp::start_application_processors() and causality::init(); bootloader
+/// responses must not be read afterwards.
+pub fn reclaim_bootloader() -> usize {
+    limine::release();
+    reclaim(RegionType::Bootloader, ReclaimedRegion::Bootloader)
+}
+
+/// Reclaim ACPI table memory. Returns how many frames were reclaimed.
+///
+/// Must run after everything that reads ACPI tables and after causality::init().
+pub fn reclaim_acpi() -> usize {
+    acpi::release();
+    reclaim(RegionType::AcpiReclaimable, ReclaimedRegion::AcpiReclaimable)
+}
+
+fn reclaim(kind: RegionType, region: ReclaimedRegion) -> usize {
+    let frames = frame::reclaim(limine::memory_map(), kind);
+    context::emit(
+        EventKind::MemoryReclaim,
+        RootCause::Boot,
+        EventData::Reclaim { region: region as u8, frames: frames as u64 },
+    );
+    frames
+}
@@ -0,0 +1,574 @@
+//! Object caches for fixed-size kernel objects.
+//!
+//! A `SlabCache` hands out objects of one size carved from slabs. Each slab lives in its
+//! own SLAB_SLOT_SIZE-aligned slot of a dedicated virtual region, starts with a header
+//! and tracks free objects in a bitmap, so the allocator never writes into an object and
+//! the state left by the constructor survives free/alloc cycles. The owning slab of any
+//! object is found by masking its address.
+//!
+//! Every CPU has a magazine of free objects per cache, so the common alloc and free only
+//! take that CPU's uncontended lock; the shared slab lists are touched when a magazine
+//! runs empty or full.
+//!
+//! Caches are plain statics and register themselves on first use. With the `slab-events`
+//! feature every alloc and free records a `SlabAlloc`/`SlabFree` event so objects that
+//! are never freed can be traced back to the context that allocated them; outside of
+//! any context the event is rooted at `RootCause::Unattributed`.
+
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::{NonNull, null_mut};
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+use crate::arch::x86_64::cpu::{self, MAX_CPUS};
+use crate::causality::types::EventKind;
+use crate::println;
+use crate::sync::SpinLock;
+
+use super::page::{self, PageFlags};
+use super::frame;
+
+const PAGE_SIZE: usize = 4096;
+const SLAB_BASE: u64 = 0xffff_c100_0000_0000;
+const SLAB_REGION_SIZE: u64 = 1 << 30;
+const SLAB_SLOT_SIZE: usize = 64 * 1024;
+const SLOT_COUNT: usize = (SLAB_REGION_SIZE / SLAB_SLOT_SIZE as u64) as usize;
+
+/// Largest object a cache can hold.
+pub const MAX_OBJECT_SIZE: usize = PAGE_SIZE;
+/// Slabs are sized to hold at least this many objects, within the slot.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+const MAX_OBJECTS_PER_SLAB: usize = 256;
+const BITMAP_WORDS: usize = MAX_OBJECTS_PER_SLAB / 64;
+
+const MAGAZINE_SIZE: usize = 16;
+/// Objects moved between a magazine and the slab lists at once.
+const MAGAZINE_BATCH: usize = MAGAZINE_SIZE / 2;
+
+const MAX_CACHES: usize = 32;
+const UNREGISTERED: u16 = u16::MAX;
+
+static HHDM_OFFSET: AtomicU64 = AtomicU64::new(0);
+static SLOTS: SpinLock<SlotMap> = SpinLock::new(SlotMap::new());
+static REGISTRY: SpinLock<Registry> = SpinLock::new(Registry::new());
+
+/// General-purpose caches for power-of-two sizes, each naturally aligned.
+static SIZE_CLASSES: [SlabCache; 9] = [
+    SlabCache::new("size-16", 16, 16, None),
+    SlabCache::new("size-32", 32, 32, None),
+    SlabCache::new("size-64", 64, 64, None),
+    SlabCache::new("size-128", 128, 128, None),
+    SlabCache::new("size-256", 256, 256, None),
+    SlabCache::new("size-512", 512, 512, None),
+    SlabCache::new("size-1024", 1024, 1024, None),
+    SlabCache::new("size-2048", 2048, 2048, None),
+    SlabCache::new("size-4096", 4096, 4096, None),
+];
+
+#[derive(Debug)]
+pub enum SlabError {
+    OutOfFrames,
+    MapFailed,
+    /// The slab region has no free slot left.
+    Exhausted,
+    /// The object is larger than MAX_OBJECT_SIZE.
+    TooLarge,
+}
+
+/// Occupancy of one cache at the time it was sampled.
+#[derive(Clone, Copy, Debug)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub slabs: usize,
+    /// Objects the slabs can hold in total.
+    pub capacity: usize,
+    /// Objects held by callers.
+    pub in_use: usize,
+    /// Free objects parked in per-CPU magazines.
+    pub cached: usize,
+}
+
+/// Header at the start of every slab slot.
+#[repr(C)]
+struct Slab {
+    cache: *const SlabCache,
+    prev: *mut Slab,
+    next: *mut Slab,
+    /// Set bits mark free objects.
+    free: [u64; BITMAP_WORDS],
+    in_use: usize,
+}
+
+/// Doubly linked list of slabs in one occupancy state.
+struct SlabList {
+    head: *mut Slab,
+}
+
+/// Slab lists of a cache, shared between CPUs.
+struct Depot {
+    /// Some objects free.
+    partial: SlabList,
+    /// No object free.
+    full: SlabList,
+    /// Every object free.
+    empty: SlabList,
+    slabs: usize,
+    /// Objects out of the slabs, in magazines or held by callers.
+    allocated: usize,
+}
+
+struct Magazine {
+    objects: [*mut u8; MAGAZINE_SIZE],
+    count: usize,
+}
+
+unsafe impl Send for Depot {}
+unsafe impl Send for Magazine {}
+
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    /// Distance between consecutive objects.
+    stride: usize,
+    /// Offset of the first object from the slab header.
+    first_object: usize,
+    pages_per_slab: usize,
+    objects_per_slab: usize,
+    /// Runs once on every object when its slab is created. Callers must free objects
+    /// in their constructed state.
+    constructor: Option<fn(*mut u8)>,
+    /// Index in the registry, used as the cache id in events.
+    id: AtomicU16,
+    depot: SpinLock<Depot>,
+    magazines: [SpinLock<Magazine>; MAX_CPUS],
+}
+
+impl SlabCache {
+    /// A cache for objects of `object_size` bytes aligned to `align`, a power of two.
+    pub const fn new(name: &'static str, object_size: usize, align: usize, constructor: Option<fn(*mut u8)>) -> Self {
+        assert!(object_size > 0 && object_size <= MAX_OBJECT_SIZE, "slab object size out of range");
+        assert!(align.is_power_of_two() && align <= PAGE_SIZE, "slab object alignment out of range");
+
+        let stride = object_size.next_multiple_of(align);
+        let first_object = size_of::<Slab>().next_multiple_of(align);
+
+        let mut pages_per_slab = (first_object + MIN_OBJECTS_PER_SLAB * stride).div_ceil(PAGE_SIZE);
+        if pages_per_slab * PAGE_SIZE > SLAB_SLOT_SIZE {
+            pages_per_slab = SLAB_SLOT_SIZE / PAGE_SIZE;
+        }
+        let mut objects_per_slab = (pages_per_slab * PAGE_SIZE - first_object) / stride;
+        if objects_per_slab > MAX_OBJECTS_PER_SLAB {
+            objects_per_slab = MAX_OBJECTS_PER_SLAB;
+        }
+
+        Self {
+            name,
+            object_size,
+            stride,
+            first_object,
+            pages_per_slab,
+            objects_per_slab,
+            constructor,
+            id: AtomicU16::new(UNREGISTERED),
+            depot: SpinLock::new(Depot {
+                partial: SlabList::new(),
+                full: SlabList::new(),
+                empty: SlabList::new(),
+                slabs: 0,
+                allocated: 0,
+            }),
+            magazines: [const { SpinLock::new(Magazine::new()) }; MAX_CPUS],
+        }
+    }
+
+    /// Take an object from the calling CPU's magazine, refilling it from the slabs when
+    /// empty. cpu::init() and slab::init() must have run.
+    pub fn alloc(&'static self) -> Result<NonNull<u8>, SlabError> {
+        let object = {
+            let mut magazine = self.magazines[cpu::current_core_id() as usize].lock();
+            if magazine.count == 0 {
+                self.refill(&mut magazine)?;
+            }
+            magazine.pop()
+        };
+
+        record(EventKind::SlabAlloc, self, object);
+        Ok(unsafe { NonNull::new_unchecked(object) })
+    }
+
+    /// Return an object to the calling CPU's magazine, spilling to the slabs when full.
+    pub fn free(&'static self, object: NonNull<u8>) {
+        let object = object.as_ptr();
+        record(EventKind::SlabFree, self, object);
+
+        let mut magazine = self.magazines[cpu::current_core_id() as usize].lock();
+        if magazine.count == MAGAZINE_SIZE {
+            self.spill(&mut magazine);
+        }
+        magazine.push(object);
+    }
+
+    /// Release empty slabs back to the frame allocator. Returns how many were released.
+    #[allow(dead_code, reason = "for reclaiming memory under pressure, which nothing detects yet")]
+    pub fn shrink(&'static self) -> usize {
+        let mut depot = self.depot.lock();
+        let mut released = 0;
+
+        while let Some(slab) = depot.empty.pop() {
+            self.release_slab(slab);
+            depot.slabs -= 1;
+            released += 1;
+        }
+
+        released
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let cached: usize = self.magazines.iter().map(|magazine| magazine.lock().count).sum();
+        let depot = self.depot.lock();
+
+        SlabStats {
+            name: self.name,
+            object_size: self.object_size,
+            slabs: depot.slabs,
+            capacity: depot.slabs * self.objects_per_slab,
+            in_use: depot.allocated.saturating_sub(cached),
+            cached,
+        }
+    }
+
+    fn refill(&'static self, magazine: &mut Magazine) -> Result<(), SlabError> {
+        let mut depot = self.depot.lock();
+
+        while magazine.count < MAGAZINE_BATCH {
+            let slab = match depot.partial.head.is_null() {
+                false => depot.partial.head,
+                true => match depot.empty.pop() {
+                    Some(slab) => {
+                        depot.partial.push(slab);
+                        slab
+                    }
+                    None if magazine.count > 0 => break,
+                    None => {
+                        let slab = self.create_slab()?;
+                        depot.slabs += 1;
+                        depot.partial.push(slab);
+                        slab
+                    }
+                },
+            };
+
+            magazine.push(self.take_object(slab));
+            depot.allocated += 1;
+
+            if unsafe { (*slab).in_use } == self.objects_per_slab {
+                depot.partial.remove(slab);
+                depot.full.push(slab);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn spill(&'static self, magazine: &mut Magazine) {
+        let mut depot = self.depot.lock();
+
+        for _ in 0..MAGAZINE_BATCH {
+            let object = magazine.pop();
+            let slab = slab_of(object);
+            let was_full = unsafe { (*slab).in_use } == self.objects_per_slab;
+
+            self.return_object(slab, object);
+            depot.allocated -= 1;
+
+            let now_empty = unsafe { (*slab).in_use } == 0;
+            match (was_full, now_empty) {
+                (true, true) => {
+                    depot.full.remove(slab);
+                    depot.empty.push(slab);
+                }
+                (true, false) => {
+                    depot.full.remove(slab);
+                    depot.partial.push(slab);
+                }
+                (false, true) => {
+                    depot.partial.remove(slab);
+                    depot.empty.push(slab);
+                }
+                (false, false) => {}
+            }
+        }
+    }
+
+    fn take_object(&self, slab: *mut Slab) -> *mut u8 {
+        let slab = unsafe { &mut *slab };
+        let (word, bits) = slab
+            .free
+            .iter_mut()
+            .enumerate()
+            .find(|(_, bits)| **bits != 0)
+            .expect("Partial slab should have a free object");
+
+        let bit = bits.trailing_zeros() as usize;
+        *bits &= !(1 << bit);
+        slab.in_use += 1;
+
+        let index = word * 64 + bit;
+        (slab as *mut Slab as usize + self.first_object + index * self.stride) as *mut u8
+    }
+
+    fn return_object(&self, slab: *mut Slab, object: *mut u8) {
+        let slab = unsafe { &mut *slab };
+        let offset = (object as usize)
+            .checked_sub(slab as *mut Slab as usize + self.first_object)
+            .filter(|offset| offset % self.stride == 0 && offset / self.stride < self.objects_per_slab)
+            .unwrap_or_else(|| panic!("Freed pointer {:p} is not an object of slab cache {}", object, self.name));
+
+        let index = offset / self.stride;
+        let (word, bit) = (index / 64, index % 64);
+        if slab.free[word] & (1 << bit) != 0 {
+            panic!("Double free of {:p} in slab cache {}", object, self.name);
+        }
+        slab.free[word] |= 1 << bit;
+        slab.in_use -= 1;
+    }
+
+    /// Map a slab in a free slot, register the cache on its first slab and construct
+    /// every object.
+    fn create_slab(&'static self) -> Result<*mut Slab, SlabError> {
+        if self.id.load(Ordering::Relaxed) == UNREGISTERED {
+            REGISTRY.lock().register(self);
+        }
+
+        let base = SLOTS.lock().claim().ok_or(SlabError::Exhausted)?;
+        let hhdm_offset = HHDM_OFFSET.load(Ordering::Relaxed);
+
+        for page_index in 0..self.pages_per_slab {
+            let virt_addr = base + (page_index * PAGE_SIZE) as u64;
+            let mapped = frame::alloc().ok_or(SlabError::OutOfFrames).and_then(|phys_addr| {
+                page::map(virt_addr, phys_addr, PageFlags::WRITABLE, hhdm_offset)
+                    .map_err(|_| {
+                        frame::free(phys_addr);
+                        SlabError::MapFailed
+                    })
+            });
+
+            if let Err(err) = mapped {
+                unmap_pages(base, page_index, hhdm_offset);
+                SLOTS.lock().release(base);
+                return Err(err);
+            }
+        }
+
+        let slab = base as *mut Slab;
+        let mut free = [0u64; BITMAP_WORDS];
+        for index in 0..self.objects_per_slab {
+            free[index / 64] |= 1 << (index % 64);
+        }
+        unsafe {
+            slab.write(Slab {
+                cache: self,
+                prev: null_mut(),
+                next: null_mut(),
+                free,
+                in_use: 0,
+            });
+        }
+
+        if let Some(constructor) = self.constructor {
+            for index in 0..self.objects_per_slab {
+                constructor((base as usize + self.first_object + index * self.stride) as *mut u8);
+            }
+        }
+
+        Ok(slab)
+    }
+
+    fn release_slab(&self, slab: *mut Slab) {
+        let base = slab as u64;
+        unmap_pages(base, self.pages_per_slab, HHDM_OFFSET.load(Ordering::Relaxed));
+        SLOTS.lock().release(base);
+    }
+}
+
+impl SlabList {
+    const fn new() -> Self {
+        Self { head: null_mut() }
+    }
+
+    fn push(&mut self, slab: *mut Slab) {
+        unsafe {
+            (*slab).prev = null_mut();
+            (*slab).next = self.head;
+            if !self.head.is_null() {
+                (*self.head).prev = slab;
+            }
+        }
+        self.head = slab;
+    }
+
+    fn remove(&mut self, slab: *mut Slab) {
+        unsafe {
+            let (prev, next) = ((*slab).prev, (*slab).next);
+            match prev.is_null() {
+                true => self.head = next,
+                false => (*prev).next = next,
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<*mut Slab> {
+        let slab = self.head;
+        if slab.is_null() {
+            return None;
+        }
+        self.remove(slab);
+        Some(slab)
+    }
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            objects: [null_mut(); MAGAZINE_SIZE],
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, object: *mut u8) {
+        self.objects[self.count] = object;
+        self.count += 1;
+    }
+
+    fn pop(&mut self) -> *mut u8 {
+        self.count -= 1;
+        self.objects[self.count]
+    }
+}
+
+/// Which slab slots are in use.
+struct SlotMap {
+    used: [u64; SLOT_COUNT / 64],
+}
+
+impl SlotMap {
+    const fn new() -> Self {
+        Self {
+            used: [0; SLOT_COUNT / 64],
+        }
+    }
+
+    fn claim(&mut self) -> Option<u64> {
+        let (word, bits) = self.used.iter_mut().enumerate().find(|(_, bits)| **bits != u64::MAX)?;
+        let bit = bits.trailing_ones() as usize;
+        *bits |= 1 << bit;
+        Some(SLAB_BASE + ((word * 64 + bit) * SLAB_SLOT_SIZE) as u64)
+    }
+
+    fn release(&mut self, base: u64) {
+        let slot = ((base - SLAB_BASE) as usize) / SLAB_SLOT_SIZE;
+        self.used[slot / 64] &= !(1 << (slot % 64));
+    }
+}
+
+struct Registry {
+    caches: [Option<&'static SlabCache>; MAX_CACHES],
+    count: usize,
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            caches: [None; MAX_CACHES],
+            count: 0,
+        }
+    }
+
+    /// Add `cache` unless another CPU registered it first. Caches beyond MAX_CACHES
+    /// still work but are missing from stats() and report an unknown id in events.
+    fn register(&mut self, cache: &'static SlabCache) {
+        if cache.id.load(Ordering::Relaxed) != UNREGISTERED || self.count == MAX_CACHES {
+            return;
+        }
+        self.caches[self.count] = Some(cache);
+        cache.id.store(self.count as u16, Ordering::Relaxed);
+        self.count += 1;
+    }
+}
+
+/// Record where slabs are mapped from. Must run before the first allocation.
+pub fn init(hhdm_offset: u64) {
+    HHDM_OFFSET.store(hhdm_offset, Ordering::Relaxed);
+}
+
+/// Allocate from the smallest size class that fits `layout`, which must fit in
+/// MAX_OBJECT_SIZE.
+pub fn alloc(layout: Layout) -> Result<NonNull<u8>, SlabError> {
+    let size = layout.size().max(layout.align());
+    let cache = SIZE_CLASSES
+        .iter()
+        .find(|cache| cache.object_size >= size)
+        .ok_or(SlabError::TooLarge)?;
+    cache.alloc()
+}
+
+/// Free an object from any cache, found through the slab that holds it.
+pub fn free(object: NonNull<u8>) {
+    let slab = slab_of(object.as_ptr());
+    let cache = unsafe { &*(*slab).cache };
+    cache.free(object);
+}
+
+/// Whether `object` lies in the slab region, and so must be freed with free().
+pub fn owns(object: *const u8) -> bool {
+    (SLAB_BASE..SLAB_BASE + SLAB_REGION_SIZE).contains(&(object as u64))
+}
+
+/// Occupancy of every registered cache.
+pub fn stats() -> impl Iterator<Item = SlabStats> {
+    let caches = REGISTRY.lock().caches;
+    caches.into_iter().flatten().map(SlabCache::stats)
+}
+
+/// Print the occupancy of every registered cache.
+pub fn print_summary() {
+    println!("{:<12} {:>6} {:>6} {:>8} {:>6} {:>6}", "cache", "size", "slabs", "capacity", "in use", "cached");
+    for stats in stats() {
+        let SlabStats { name, object_size, slabs, capacity, in_use, cached } = stats;
+        println!("{:<12} {:>6} {:>6} {:>8} {:>6} {:>6}", name, object_size, slabs, capacity, in_use, cached);
+    }
+}
+
+fn slab_of(object: *mut u8) -> *mut Slab {
+    if !owns(object) {
+        panic!("Freed pointer {:p} is outside the slab region", object);
+    }
+    let addr = object as u64;
+    (addr & !(SLAB_SLOT_SIZE as u64 - 1)) as *mut Slab
+}
+
+/// Unmap the first `pages` pages at `base` and return their frames.
+fn unmap_pages(base: u64, pages: usize, hhdm_offset: u64) {
+    for page_index in 0..pages {
+        if let Ok(phys_addr) = page::unmap(base + (page_index * PAGE_SIZE) as u64, hhdm_offset) {
+            frame::free(phys_addr);
+        }
+    }
+}
+
+#[cfg(feature = "slab-events")]
+fn record(kind: EventKind, cache: &SlabCache, object: *mut u8) {
+    use crate::causality::{self, context, types::{EventData, RootCause}};
+
+    if causality::is_initialized() {
+        let data = EventData::Object { cache: cache.id.load(Ordering::Relaxed), addr: object as u64 };
+        context::emit(kind, RootCause::Unattributed, data);
+    }
+}
+
+#[cfg(not(feature = "slab-events"))]
+fn record(_kind: EventKind, _cache: &SlabCache, _object: *mut u8) {}
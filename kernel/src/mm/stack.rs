@@ -1,9 +1,11 @@
 use super::{frame, page};
-use super::page::PageTableEntry;
+use super::page::PageFlags;
 
 const KERNEL_STACK_BASE: u64 = 0xffffffff90000000;
 const KERNEL_STACK_PAGES: usize = 4;
 const PAGE_SIZE: u64 = 4096;
+/// Guard page plus stack pages.
+const STACK_SLOT_SIZE: u64 = (KERNEL_STACK_PAGES as u64 + 1) * PAGE_SIZE;
 
 #[derive(Debug)]
 pub enum StackError {
@@ -11,15 +13,24 @@ pub enum StackError {
     MapFailed,
 }
 
+/// Stack of the bootstrap processor.
 pub fn allocate_kernel_stack(hhdm_offset: u64) -> Result<u64, StackError> {
+    allocate_stack(0, hhdm_offset)
+}
+
+/// Map the `slot`-th kernel stack below a guard page and return its top. Slot 0 is the
+/// bootstrap processor's; application processors use the following slots.
+pub fn allocate_stack(slot: usize, hhdm_offset: u64) -> Result<u64, StackError> {
+    let base = KERNEL_STACK_BASE + slot as u64 * STACK_SLOT_SIZE;
+
     for i in 1..=KERNEL_STACK_PAGES {
-        let virt_addr = KERNEL_STACK_BASE + (i as u64 * PAGE_SIZE);
+        let virt_addr = base + (i as u64 * PAGE_SIZE);
         let phys_addr = frame::alloc().ok_or(StackError::OutofFrames)?;
-        page::map(virt_addr, phys_addr, PageTableEntry::PRESENT | PageTableEntry::WRITABLE, hhdm_offset)
+        page::map(virt_addr, phys_addr, PageFlags::WRITABLE, hhdm_offset)
             .map_err(|_| StackError::MapFailed)?;
     }
 
-    page::map_guard(KERNEL_STACK_BASE, hhdm_offset).map_err(|_| StackError::MapFailed)?;
+    page::map_guard(base, hhdm_offset).map_err(|_| StackError::MapFailed)?;
 
-    Ok(KERNEL_STACK_BASE + ((KERNEL_STACK_PAGES + 1) as u64 * PAGE_SIZE))
+    Ok(base + STACK_SLOT_SIZE)
 }
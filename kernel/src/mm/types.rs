@@ -1,25 +1,30 @@
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RegionType {
     Usable,
     Reserved,
     AcpiReclaimable,
     Bootloader,
+    /// Taken by the kernel before the frame allocator existed.
+    Kernel,
     Unknown,
 }
 
+impl RegionType {
+    pub const fn name(self) -> &'static str {
+        match self {
+            RegionType::Usable => "usable",
+            RegionType::Reserved => "reserved",
+            RegionType::AcpiReclaimable => "acpi reclaimable",
+            RegionType::Bootloader => "bootloader",
+            RegionType::Kernel => "kernel",
+            RegionType::Unknown => "unknown",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct MemoryRegion {
     pub base: u64,
     pub length: u64,
     pub kind: RegionType,
 }
-
-impl MemoryRegion {
-    pub const fn empty() -> Self {
-        Self {
-            base: 0,
-            length: 0,
-            kind: RegionType::Unknown,
-        }
-    }
-}
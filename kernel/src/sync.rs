@@ -0,0 +1,118 @@
+//! Spinlock for short critical sections shared between CPUs.
+//!
+//! Interrupts are disabled on the holding CPU for as long as the guard lives, so an
+//! interrupt handler that takes the same lock cannot deadlock against the code it
+//! interrupted.
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const RFLAGS_IF: u64 = 1 << 9;
+
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let interrupts_were_enabled = disable_interrupts();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+
+        SpinLockGuard {
+            lock: self,
+            interrupts_were_enabled,
+        }
+    }
+
+    /// Take the lock if it is free, without spinning.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let interrupts_were_enabled = disable_interrupts();
+        match self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Some(SpinLockGuard {
+                lock: self,
+                interrupts_were_enabled,
+            }),
+            Err(_) => {
+                if interrupts_were_enabled {
+                    unsafe { asm!("sti", options(nomem, nostack)) };
+                }
+                None
+            }
+        }
+    }
+
+    /// Take the lock whether or not it is held.
+    ///
+    /// # Safety
+    /// The holder, if any, must never touch the value again, as on the panic path when
+    /// it is this CPU's own interrupted code or a CPU that stopped making progress.
+    pub unsafe fn force_lock(&self) -> SpinLockGuard<'_, T> {
+        let interrupts_were_enabled = disable_interrupts();
+        self.locked.swap(true, Ordering::Acquire);
+        SpinLockGuard {
+            lock: self,
+            interrupts_were_enabled,
+        }
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    interrupts_were_enabled: bool,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        if self.interrupts_were_enabled {
+            unsafe { asm!("sti", options(nomem, nostack)) };
+        }
+    }
+}
+
+/// Clear IF and return whether it was set.
+fn disable_interrupts() -> bool {
+    let rflags: u64;
+    unsafe {
+        asm!(
+            "pushfq",
+            "pop {}",
+            "cli",
+            out(reg) rflags,
+            options(nomem, preserves_flags)
+        );
+    }
+    rflags & RFLAGS_IF != 0
+}
@@ -0,0 +1,248 @@
+//! Causal DAG reconstructed from decoded events.
+
+use std::collections::BTreeMap;
+
+use causality_core::types::{Cause, Event, EventData, EventId};
+
+use crate::decode::Record;
+
+/// A range of sequence numbers a core declared lost with an `EventKind::Loss` event.
+#[derive(Clone, Copy, Debug)]
+pub struct LossRange {
+    pub reported_by: EventId,
+    pub core: u16,
+    pub first_sequence: u64,
+    pub last_sequence: u64,
+}
+
+impl LossRange {
+    pub fn contains(&self, id: EventId) -> bool {
+        id.core() == self.core && (self.first_sequence..=self.last_sequence).contains(&id.sequence())
+    }
+}
+
+/// The causal DAG of a single boot. Sequence numbers restart on every boot, so events of
+/// different boots share ids and each boot gets its own DAG.
+#[derive(Debug, Default)]
+pub struct CausalDag {
+    /// Index of the boot, see `Record::boot`.
+    boot: usize,
+    /// Retained events, ordered by (core, sequence).
+    events: BTreeMap<EventId, Event>,
+    /// Segment of the record each retained event was first seen in.
+    segments: BTreeMap<EventId, usize>,
+    /// Child edges keyed by parent, including parents that were not retained.
+    children: BTreeMap<EventId, Vec<EventId>>,
+    losses: Vec<LossRange>,
+    /// Ids seen more than once with different contents.
+    conflicts: Vec<EventId>,
+}
+
+impl CausalDag {
+    /// Build the DAG from the decoded records of a single boot, see `Capture::boots`.
+    /// Identical re-sends of an event (e.g. a panic dump repeating already streamed
+    /// events) are merged.
+    pub fn build(records: &[Record]) -> Self {
+        let mut dag = Self::default();
+        if let Some(first) = records.first() {
+            dag.boot = first.boot;
+        }
+
+        for record in records {
+            debug_assert_eq!(record.boot, dag.boot, "records of different boots");
+            let event = record.event;
+            if let Some(existing) = dag.events.get(&event.id) {
+                if *existing != event && !dag.conflicts.contains(&event.id) {
+                    dag.conflicts.push(event.id);
+                }
+                continue;
+            }
+
+            dag.events.insert(event.id, event);
+            dag.segments.insert(event.id, record.segment);
+
+            if let Cause::CausedBy(parent) = event.cause {
+                dag.children.entry(parent).or_default().push(event.id);
+            }
+
+            if let EventData::Loss { core, first_sequence, last_sequence } = event.data {
+                dag.losses.push(LossRange {
+                    reported_by: event.id,
+                    core,
+                    first_sequence,
+                    last_sequence,
+                });
+            }
+        }
+
+        for children in dag.children.values_mut() {
+            children.sort();
+        }
+
+        dag
+    }
+
+    pub fn boot(&self) -> usize {
+        self.boot
+    }
+
+    /// Index into `Capture::headers` of the stream segment a retained event came from.
+    pub fn segment(&self, id: EventId) -> Option<usize> {
+        self.segments.get(&id).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn get(&self, id: EventId) -> Option<&Event> {
+        self.events.get(&id)
+    }
+
+    /// Retained events ordered by core, then sequence.
+    pub fn events(&self) -> impl Iterator<Item = &Event> {
+        self.events.values()
+    }
+
+    /// Retained events of a single core in sequence order.
+    pub fn core_events(&self, core: u16) -> impl Iterator<Item = &Event> {
+        self.events
+            .range(EventId::new(core, 0)..=EventId::new(core, u64::MAX))
+            .map(|(_, event)| event)
+    }
+
+    /// Cores that have at least one retained event, ascending.
+    pub fn cores(&self) -> Vec<u16> {
+        let mut cores: Vec<u16> = self.events.keys().map(|id| id.core()).collect();
+        cores.dedup();
+        cores
+    }
+
+    pub fn parent(&self, id: EventId) -> Option<EventId> {
+        match self.events.get(&id)?.cause {
+            Cause::CausedBy(parent) => Some(parent),
+            Cause::Root(_) => None,
+        }
+    }
+
+    pub fn children(&self, id: EventId) -> &[EventId] {
+        self.children.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Events that start a causal chain.
+    pub fn roots(&self) -> impl Iterator<Item = &Event> {
+        self.events
+            .values()
+            .filter(|event| matches!(event.cause, Cause::Root(_)))
+    }
+
+    /// All `CausedBy` edges as (parent, child), whether or not the parent was retained.
+    pub fn edges(&self) -> impl Iterator<Item = (EventId, EventId)> + '_ {
+        self.children
+            .iter()
+            .flat_map(|(parent, children)| children.iter().map(move |child| (*parent, *child)))
+    }
+
+    pub fn losses(&self) -> &[LossRange] {
+        &self.losses
+    }
+
+    /// Whether `id` falls inside a range some core declared lost.
+    pub fn is_dropped(&self, id: EventId) -> bool {
+        self.losses.iter().any(|loss| loss.contains(id))
+    }
+
+    pub fn conflicts(&self) -> &[EventId] {
+        &self.conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use causality_core::types::{EventKind, RootCause};
+
+    use super::*;
+
+    fn record(segment: usize, dumped: bool, event: Event) -> Record {
+        Record { offset: 0, segment, boot: 0, dumped, event }
+    }
+
+    fn event(core: u16, sequence: u64, cause: Cause) -> Event {
+        Event {
+            id: EventId::new(core, sequence),
+            timestamp: sequence,
+            kind: EventKind::Boot,
+            cause,
+            data: EventData::None,
+        }
+    }
+
+    fn caused_by(core: u16, sequence: u64) -> Cause {
+        Cause::CausedBy(EventId::new(core, sequence))
+    }
+
+    #[test]
+    fn links_children_and_keeps_edges_to_unretained_parents() {
+        let root = event(0, 0, Cause::Root(RootCause::Boot));
+        let records = [
+            record(0, false, root),
+            record(0, false, event(1, 0, caused_by(0, 0))),
+            record(0, false, event(0, 2, caused_by(0, 0))),
+            record(0, false, event(0, 3, caused_by(0, 1))),
+        ];
+        let dag = CausalDag::build(&records);
+
+        assert_eq!(dag.len(), 4);
+        assert_eq!(dag.cores(), [0, 1]);
+        assert_eq!(dag.roots().copied().collect::<Vec<_>>(), [root]);
+        assert_eq!(dag.children(root.id), [EventId::new(0, 2), EventId::new(1, 0)]);
+        assert_eq!(dag.parent(EventId::new(0, 3)), Some(EventId::new(0, 1)));
+        assert_eq!(dag.get(EventId::new(0, 1)), None);
+        assert!(dag.edges().any(|edge| edge == (EventId::new(0, 1), EventId::new(0, 3))));
+        assert_eq!(dag.core_events(0).map(|event| event.id.sequence()).collect::<Vec<_>>(), [0, 2, 3]);
+    }
+
+    #[test]
+    fn merges_dumped_repeats_and_flags_conflicting_ones() {
+        let streamed = event(0, 0, Cause::Root(RootCause::Boot));
+        let conflicting = event(0, 1, caused_by(0, 0));
+        let changed = Event { timestamp: 99, ..conflicting };
+        let records = [
+            record(0, false, streamed),
+            record(0, false, conflicting),
+            record(1, true, streamed),
+            record(1, true, changed),
+            record(1, true, event(0, 2, caused_by(0, 1))),
+        ];
+        let dag = CausalDag::build(&records);
+
+        assert_eq!(dag.len(), 3);
+        assert_eq!(dag.children(streamed.id), [conflicting.id]);
+        assert_eq!(dag.conflicts(), [conflicting.id]);
+        assert_eq!(dag.segment(streamed.id), Some(0));
+        assert_eq!(dag.segment(EventId::new(0, 2)), Some(1));
+    }
+
+    #[test]
+    fn collects_loss_ranges() {
+        let loss = Event {
+            id: EventId::new(2, 9),
+            timestamp: 0,
+            kind: EventKind::Loss,
+            cause: Cause::Root(RootCause::Overflow),
+            data: EventData::Loss { core: 2, first_sequence: 4, last_sequence: 8 },
+        };
+        let dag = CausalDag::build(&[record(0, false, loss)]);
+
+        assert_eq!(dag.losses().len(), 1);
+        assert_eq!(dag.losses()[0].reported_by, loss.id);
+        assert!(dag.is_dropped(EventId::new(2, 4)));
+        assert!(dag.is_dropped(EventId::new(2, 8)));
+        assert!(!dag.is_dropped(EventId::new(2, 9)));
+        assert!(!dag.is_dropped(EventId::new(1, 5)));
+    }
+}
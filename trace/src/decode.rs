@@ -0,0 +1,216 @@
+//! Recovers wire-format records from a raw serial capture.
+//!
+//! The kernel shares COM1 between console text and event records, so the decoder scans
+//! byte by byte and only accepts headers and records whose framing and checksum verify.
+
+use causality_core::types::Event;
+use causality_core::wire::{
+    self, DecodeError, HEADER_FLAG_PANIC_DUMP, HEADER_LEN, Header, RECORD_LEN, RECORD_SYNC,
+    RECORD_TYPE_DUMPED_EVENT, STREAM_MAGIC,
+};
+
+/// An event together with where it was found in the capture.
+#[derive(Clone, Copy, Debug)]
+pub struct Record {
+    /// Byte offset of the record in the capture.
+    pub offset: usize,
+    /// Index of the stream header this record follows. A new header starts a new
+    /// segment, e.g. after a reboot or a panic dump.
+    pub segment: usize,
+    /// Index of the boot the record belongs to. Every header except a panic dump's
+    /// starts a new boot, and sequence numbers restart with it.
+    pub boot: usize,
+    /// Re-sent by a panic dump: newest first and possibly a repeat of a streamed event.
+    pub dumped: bool,
+    pub event: Event,
+}
+
+#[derive(Debug, Default)]
+pub struct Capture {
+    /// Records in the order they appeared on the wire.
+    pub records: Vec<Record>,
+    /// Stream headers in capture order; `Record::segment` indexes into this.
+    pub headers: Vec<Header>,
+    /// Number of boots the headers start.
+    pub boots: usize,
+    /// Headers and records that framed correctly but could not be decoded.
+    pub rejected: Vec<(usize, DecodeError)>,
+    /// Bytes that were not part of any header or record (console text, noise).
+    pub skipped_bytes: usize,
+}
+
+pub fn decode(bytes: &[u8]) -> Capture {
+    let mut capture = Capture::default();
+    let mut in_stream = false;
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+
+        if let Some(header) = rest.first_chunk::<HEADER_LEN>()
+            && header.starts_with(&STREAM_MAGIC)
+        {
+            match wire::decode_header(header) {
+                Ok(header) => {
+                    // A dump with no header before it still belongs to some boot.
+                    if header.flags & HEADER_FLAG_PANIC_DUMP == 0 || capture.boots == 0 {
+                        capture.boots += 1;
+                    }
+                    capture.headers.push(header);
+                    in_stream = true;
+                    pos += HEADER_LEN;
+                    continue;
+                }
+                Err(err @ (DecodeError::UnsupportedVersion(_) | DecodeError::BadLength)) => {
+                    // Records that follow use a layout this decoder does not know.
+                    capture.rejected.push((pos, err));
+                    in_stream = false;
+                    pos += HEADER_LEN;
+                    continue;
+                }
+                Err(_) => {}
+            }
+        }
+
+        if in_stream
+            && let Some(record) = rest.first_chunk::<RECORD_LEN>()
+            && record[..2] == RECORD_SYNC.to_le_bytes()
+        {
+            match wire::decode_event(record) {
+                Ok((record_type, event)) => {
+                    capture.records.push(Record {
+                        offset: pos,
+                        segment: capture.headers.len() - 1,
+                        boot: capture.boots - 1,
+                        dumped: record_type == RECORD_TYPE_DUMPED_EVENT,
+                        event,
+                    });
+                    pos += RECORD_LEN;
+                    continue;
+                }
+                // Sync word inside console text or a corrupted record, keep scanning.
+                Err(DecodeError::BadChecksum) => {}
+                Err(err) => {
+                    capture.rejected.push((pos, err));
+                    pos += RECORD_LEN;
+                    continue;
+                }
+            }
+        }
+
+        capture.skipped_bytes += 1;
+        pos += 1;
+    }
+
+    capture
+}
+
+impl Capture {
+    /// Records grouped by boot, in capture order. Boots without records are skipped.
+    pub fn boots(&self) -> impl Iterator<Item = &[Record]> {
+        self.records.chunk_by(|a, b| a.boot == b.boot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use causality_core::types::{Cause, EventData, EventId, EventKind, RootCause};
+    use causality_core::wire::{HEADER_FLAG_INVARIANT_TSC, RECORD_TYPE_EVENT};
+
+    use super::*;
+
+    fn header(flags: u16) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        wire::encode_header(&Header { flags, cycles_per_ns_q32: 2 << 32 }, &mut buf);
+        buf
+    }
+
+    fn record(core: u16, sequence: u64, record_type: u8) -> [u8; RECORD_LEN] {
+        let event = Event {
+            id: EventId::new(core, sequence),
+            timestamp: sequence * 100,
+            kind: EventKind::Boot,
+            cause: Cause::Root(RootCause::Boot),
+            data: EventData::None,
+        };
+        let mut buf = [0u8; RECORD_LEN];
+        wire::encode_event(&event, record_type, &mut buf);
+        buf
+    }
+
+    fn sequences(capture: &Capture) -> Vec<(usize, u16, u64)> {
+        capture
+            .records
+            .iter()
+            .map(|record| (record.boot, record.event.id.core(), record.event.id.sequence()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_records_between_console_text() {
+        let mut bytes = b"boot log\n".to_vec();
+        bytes.extend(header(HEADER_FLAG_INVARIANT_TSC));
+        bytes.extend(record(0, 0, RECORD_TYPE_EVENT));
+        bytes.extend(b"println from core 1\n");
+        bytes.extend(record(1, 0, RECORD_TYPE_EVENT));
+
+        let capture = decode(&bytes);
+        assert_eq!(capture.headers.len(), 1);
+        assert_eq!(capture.headers[0].flags, HEADER_FLAG_INVARIANT_TSC);
+        assert_eq!(sequences(&capture), [(0, 0, 0), (0, 1, 0)]);
+        assert_eq!(capture.skipped_bytes, "boot log\nprintln from core 1\n".len());
+        assert!(capture.rejected.is_empty());
+    }
+
+    #[test]
+    fn resyncs_after_a_corrupted_record() {
+        let mut corrupted = record(0, 1, RECORD_TYPE_EVENT);
+        corrupted[40] ^= 0x10;
+
+        let mut bytes = header(0).to_vec();
+        bytes.extend(record(0, 0, RECORD_TYPE_EVENT));
+        bytes.extend(corrupted);
+        bytes.extend(record(0, 2, RECORD_TYPE_EVENT));
+
+        let capture = decode(&bytes);
+        assert_eq!(sequences(&capture), [(0, 0, 0), (0, 0, 2)]);
+        assert_eq!(capture.skipped_bytes, RECORD_LEN);
+    }
+
+    #[test]
+    fn ignores_records_outside_a_known_stream() {
+        let mut newer = header(0);
+        newer[4] = 0xff;
+        let crc = wire::crc32(&newer[..20]);
+        newer[20..].copy_from_slice(&crc.to_le_bytes());
+
+        let mut bytes = record(0, 0, RECORD_TYPE_EVENT).to_vec();
+        bytes.extend(newer);
+        bytes.extend(record(0, 1, RECORD_TYPE_EVENT));
+
+        let capture = decode(&bytes);
+        assert!(capture.records.is_empty());
+        assert!(matches!(capture.rejected[..], [(RECORD_LEN, DecodeError::UnsupportedVersion(_))]));
+    }
+
+    #[test]
+    fn panic_dump_continues_the_boot_and_reboot_starts_a_new_one() {
+        let mut bytes = header(0).to_vec();
+        bytes.extend(record(0, 0, RECORD_TYPE_EVENT));
+        bytes.extend(record(0, 1, RECORD_TYPE_EVENT));
+        bytes.extend(header(HEADER_FLAG_PANIC_DUMP));
+        bytes.extend(record(0, 2, RECORD_TYPE_DUMPED_EVENT));
+        bytes.extend(record(0, 1, RECORD_TYPE_DUMPED_EVENT));
+        bytes.extend(header(0));
+        bytes.extend(record(0, 0, RECORD_TYPE_EVENT));
+
+        let capture = decode(&bytes);
+        assert_eq!(capture.boots, 2);
+        assert_eq!(sequences(&capture), [(0, 0, 0), (0, 0, 1), (0, 0, 2), (0, 0, 1), (1, 0, 0)]);
+        assert_eq!(
+            capture.records.iter().map(|record| (record.segment, record.dumped)).collect::<Vec<_>>(),
+            [(0, false), (0, false), (1, true), (1, true), (2, false)]
+        );
+        assert_eq!(capture.boots().map(<[Record]>::len).collect::<Vec<_>>(), [4, 1]);
+    }
+}
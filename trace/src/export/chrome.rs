@@ -0,0 +1,225 @@
+//! Chrome trace-event JSON export, loadable in Perfetto and `chrome://tracing`.
+//!
+//! Each boot is a process, each core a thread and each event a short slice placed at its
+//! TSC timestamp, scaled with the calibration in the header of the stream segment the
+//! event came from. Timestamps are advisory and only share a time base across cores when
+//! the TSC is invariant; without a calibration, slices fall back to their per-core
+//! sequence number. Cross-core `CausedBy`
+//! edges become flow arrows.
+
+use std::io::{self, Write};
+
+use causality_core::types::{Cause, Event};
+use causality_core::wire::Header;
+
+use super::{cause_label, data_fields, event_label, node_name};
+use crate::dag::CausalDag;
+
+/// Write every boot's DAG; `headers` is `Capture::headers`, indexed by `CausalDag::segment`.
+pub fn write(dags: &[CausalDag], headers: &[Header], out: &mut impl Write) -> io::Result<()> {
+    let mut entries = Vec::new();
+    let mut flow_ids = 0..;
+
+    for dag in dags {
+        write_boot(dag, headers, &mut flow_ids, &mut entries);
+    }
+
+    writeln!(out, "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")?;
+    for (idx, entry) in entries.iter().enumerate() {
+        let separator = if idx + 1 < entries.len() { "," } else { "" };
+        writeln!(out, "{entry}{separator}")?;
+    }
+    writeln!(out, "]}}")
+}
+
+fn write_boot(
+    dag: &CausalDag,
+    headers: &[Header],
+    flow_ids: &mut impl Iterator<Item = usize>,
+    entries: &mut Vec<String>,
+) {
+    let clock = Clock::new(dag, headers);
+    let pid = dag.boot();
+
+    entries.push(format!(
+        "{{\"ph\":\"M\",\"name\":\"process_name\",\"pid\":{pid},\"args\":{{\"name\":\"boot {pid}\"}}}}"
+    ));
+    for core in dag.cores() {
+        entries.push(format!(
+            "{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":{pid},\"tid\":{core},\"args\":{{\"name\":\"core {core}\"}}}}"
+        ));
+    }
+
+    for event in dag.events() {
+        entries.push(format!(
+            "{{\"ph\":\"X\",\"name\":\"{}\",\"cat\":\"causality\",\"pid\":{pid},\"tid\":{},\"ts\":{:.3},\"dur\":{:.3},\"args\":{{\"id\":\"{}\",\"cycles\":{},\"cause\":\"{}\",\"data\":{{{}}}}}}}",
+            escape(&event_label(event)),
+            event.id.core(),
+            clock.micros(event),
+            clock.slice_micros(event),
+            node_name(event.id),
+            event.timestamp,
+            escape(&cause_label(event.cause)),
+            data_fields(event)
+                .iter()
+                .map(|(field, value)| format!("\"{field}\":\"{}\"", escape(value)))
+                .collect::<Vec<_>>()
+                .join(",")
+        ));
+    }
+
+    for ((parent, child), flow_id) in cross_core_edges(dag).zip(flow_ids) {
+        entries.push(format!(
+            "{{\"ph\":\"s\",\"name\":\"cause\",\"cat\":\"causality\",\"id\":{flow_id},\"pid\":{pid},\"tid\":{},\"ts\":{:.3}}}",
+            parent.id.core(),
+            clock.micros(parent)
+        ));
+        entries.push(format!(
+            "{{\"ph\":\"f\",\"bp\":\"e\",\"name\":\"cause\",\"cat\":\"causality\",\"id\":{flow_id},\"pid\":{pid},\"tid\":{},\"ts\":{:.3}}}",
+            child.id.core(),
+            clock.micros(child)
+        ));
+    }
+}
+
+/// Retained (parent, child) pairs whose parent was recorded on a different core.
+fn cross_core_edges(dag: &CausalDag) -> impl Iterator<Item = (&Event, &Event)> {
+    dag.events().filter_map(|child| match child.cause {
+        Cause::CausedBy(parent) if parent.core() != child.id.core() => {
+            dag.get(parent).map(|parent| (parent, child))
+        }
+        _ => None,
+    })
+}
+
+/// Maps events of one boot to trace time in microseconds. Boots restart the TSC, so
+/// each has its own origin.
+struct Clock<'a> {
+    dag: &'a CausalDag,
+    headers: &'a [Header],
+    origin: u64,
+}
+
+impl<'a> Clock<'a> {
+    fn new(dag: &'a CausalDag, headers: &'a [Header]) -> Self {
+        let origin = dag.events().map(|event| event.timestamp).min().unwrap_or(0);
+
+        Self { dag, headers, origin }
+    }
+
+    /// TSC cycles per nanosecond from the event's segment header, None when that header
+    /// carried no calibration.
+    fn cycles_per_ns(&self, event: &Event) -> Option<f64> {
+        let header = self.headers.get(self.dag.segment(event.id)?)?;
+        Some(header.cycles_per_ns_q32 as f64 / (1u64 << 32) as f64).filter(|cycles_per_ns| *cycles_per_ns > 0.0)
+    }
+
+    fn micros(&self, event: &Event) -> f64 {
+        match self.cycles_per_ns(event) {
+            Some(cycles_per_ns) => event.timestamp.saturating_sub(self.origin) as f64 / cycles_per_ns / 1000.0,
+            None => event.id.sequence() as f64,
+        }
+    }
+
+    fn slice_micros(&self, event: &Event) -> f64 {
+        match self.cycles_per_ns(event) {
+            Some(_) => 0.001,
+            None => 1.0,
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use causality_core::types::{EventData, EventId, EventKind, RootCause};
+
+    use super::*;
+    use crate::decode::Record;
+
+    fn record(segment: usize, core: u16, sequence: u64, timestamp: u64, cause: Cause) -> Record {
+        let event = Event {
+            id: EventId::new(core, sequence),
+            timestamp,
+            kind: EventKind::Boot,
+            cause,
+            data: EventData::None,
+        };
+        Record { offset: 0, segment, boot: 0, dumped: segment > 0, event }
+    }
+
+    fn header(cycles_per_ns: u64) -> Header {
+        Header { flags: 0, cycles_per_ns_q32: cycles_per_ns << 32 }
+    }
+
+    fn render(dags: &[CausalDag], headers: &[Header]) -> String {
+        let mut out = Vec::new();
+        write(dags, headers, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn slice_ts(json: &str, id: &str) -> String {
+        let line = json.lines().find(|line| line.contains(&format!("\"id\":\"{id}\""))).unwrap();
+        let ts = &line[line.find("\"ts\":").unwrap() + 5..];
+        ts[..ts.find(',').unwrap()].to_string()
+    }
+
+    #[test]
+    fn scales_each_event_with_its_own_segment_header() {
+        let dag = CausalDag::build(&[
+            record(0, 0, 0, 1000, Cause::Root(RootCause::Boot)),
+            record(0, 0, 1, 3000, Cause::CausedBy(EventId::new(0, 0))),
+            record(1, 0, 2, 9000, Cause::CausedBy(EventId::new(0, 1))),
+        ]);
+        let json = render(&[dag], &[header(2), header(4)]);
+
+        assert_eq!(slice_ts(&json, "0:0"), "0.000");
+        assert_eq!(slice_ts(&json, "0:1"), "1.000");
+        assert_eq!(slice_ts(&json, "0:2"), "2.000");
+    }
+
+    #[test]
+    fn falls_back_to_sequence_without_calibration() {
+        let dag = CausalDag::build(&[
+            record(0, 0, 0, 1000, Cause::Root(RootCause::Boot)),
+            record(0, 0, 7, 5000, Cause::CausedBy(EventId::new(0, 0))),
+        ]);
+        let json = render(&[dag], &[header(0)]);
+
+        assert_eq!(slice_ts(&json, "0:7"), "7.000");
+        assert!(json.contains("\"dur\":1.000"));
+    }
+
+    #[test]
+    fn names_boots_and_cores_and_links_cross_core_causes() {
+        let dag = CausalDag::build(&[
+            record(0, 0, 0, 0, Cause::Root(RootCause::Boot)),
+            record(0, 1, 0, 10, Cause::CausedBy(EventId::new(0, 0))),
+        ]);
+        let json = render(&[dag], &[header(1)]);
+
+        assert!(json.starts_with("{\"displayTimeUnit\":\"ns\",\"traceEvents\":["));
+        assert!(json.contains("\"name\":\"process_name\",\"pid\":0,\"args\":{\"name\":\"boot 0\"}"));
+        assert!(json.contains("\"tid\":1,\"args\":{\"name\":\"core 1\"}"));
+        assert!(json.contains("{\"ph\":\"s\",\"name\":\"cause\",\"cat\":\"causality\",\"id\":0,\"pid\":0,\"tid\":0,"));
+        assert!(json.contains("{\"ph\":\"f\",\"bp\":\"e\",\"name\":\"cause\",\"cat\":\"causality\",\"id\":0,\"pid\":0,\"tid\":1,"));
+        assert!(json.trim_end().ends_with("]}"));
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(escape("a\"b\\c\n"), "a\\\"b\\\\c\\u000a");
+    }
+}
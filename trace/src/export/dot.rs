@@ -0,0 +1,138 @@
+//! Graphviz DOT export: one cluster per boot holding one cluster per core, `CausedBy`
+//! edges between events.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use causality_core::types::EventId;
+
+use super::{data_fields, event_label, node_name};
+use crate::dag::CausalDag;
+
+pub fn write(dags: &[CausalDag], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "digraph causality {{")?;
+    writeln!(out, "    rankdir=TB;")?;
+    writeln!(out, "    node [shape=box, fontname=monospace];")?;
+
+    for dag in dags {
+        write_boot(dag, out)?;
+    }
+
+    writeln!(out, "}}")
+}
+
+fn write_boot(dag: &CausalDag, out: &mut impl Write) -> io::Result<()> {
+    let boot = dag.boot();
+    writeln!(out, "    subgraph cluster_boot{boot} {{")?;
+    writeln!(out, "        label=\"boot {boot}\";")?;
+
+    for core in dag.cores() {
+        writeln!(out, "        subgraph cluster_boot{boot}_core{core} {{")?;
+        writeln!(out, "            label=\"core {core}\";")?;
+
+        let mut previous = None;
+        for event in dag.core_events(core) {
+            let node = node_id(boot, event.id);
+            let mut label = format!("{}\\n{}", event_label(event), node_name(event.id));
+            for (field, value) in data_fields(event) {
+                label.push_str(&format!("\\n{field}={}", escape(&value)));
+            }
+            writeln!(out, "            \"{node}\" [label=\"{label}\"];")?;
+
+            // Keep each core's events in sequence order without implying causality.
+            if let Some(previous) = previous {
+                writeln!(out, "            \"{previous}\" -> \"{node}\" [style=invis];")?;
+            }
+            previous = Some(node);
+        }
+
+        writeln!(out, "        }}")?;
+    }
+
+    let mut absent = BTreeSet::new();
+    for (parent, child) in dag.edges() {
+        if dag.get(parent).is_none() && absent.insert(parent) {
+            let style = if dag.is_dropped(parent) { "dropped" } else { "missing" };
+            writeln!(
+                out,
+                "        \"{}\" [label=\"{style}\\n{}\", style=dashed];",
+                node_id(boot, parent),
+                node_name(parent)
+            )?;
+        }
+        writeln!(out, "        \"{}\" -> \"{}\";", node_id(boot, parent), node_id(boot, child))?;
+    }
+
+    writeln!(out, "    }}")
+}
+
+/// Graph node identifier; event ids repeat across boots, so it is qualified by the boot.
+fn node_id(boot: usize, id: EventId) -> String {
+    format!("{boot}/{}", node_name(id))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use causality_core::types::{Cause, Event, EventData, EventKind, RootCause};
+
+    use super::*;
+    use crate::decode::Record;
+
+    fn record(boot: usize, core: u16, sequence: u64, cause: Cause, data: EventData) -> Record {
+        let kind = match data {
+            EventData::Loss { .. } => EventKind::Loss,
+            _ => EventKind::Boot,
+        };
+        let event = Event { id: EventId::new(core, sequence), timestamp: 0, kind, cause, data };
+        Record { offset: 0, segment: boot, boot, dumped: false, event }
+    }
+
+    fn render(dags: &[CausalDag]) -> String {
+        let mut out = Vec::new();
+        write(dags, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn renders_clusters_edges_and_absent_parents() {
+        let boot = Cause::Root(RootCause::Boot);
+        let lost = EventData::Loss { core: 0, first_sequence: 1, last_sequence: 1 };
+        let dag = CausalDag::build(&[
+            record(0, 0, 0, boot, EventData::None),
+            record(0, 1, 0, Cause::CausedBy(EventId::new(0, 0)), EventData::None),
+            record(0, 0, 3, Cause::CausedBy(EventId::new(0, 1)), EventData::None),
+            record(0, 0, 4, Cause::CausedBy(EventId::new(0, 2)), EventData::None),
+            record(0, 0, 5, Cause::Root(RootCause::Overflow), lost),
+        ]);
+        let dot = render(&[dag]);
+
+        assert!(dot.starts_with("digraph causality {"));
+        assert!(dot.contains("subgraph cluster_boot0_core1 {"));
+        assert!(dot.contains("\"0/0:0\" [label=\"Boot\\n0:0\"];"));
+        assert!(dot.contains("\"0/0:0\" -> \"0/1:0\";"));
+        assert!(dot.contains("\"0/0:0\" -> \"0/0:3\" [style=invis];"));
+        assert!(dot.contains("\"0/0:1\" [label=\"dropped\\n0:1\", style=dashed];"));
+        assert!(dot.contains("\"0/0:2\" [label=\"missing\\n0:2\", style=dashed];"));
+        assert!(dot.contains("\\nfirst_sequence=0x1"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn boots_do_not_share_nodes() {
+        let boot = Cause::Root(RootCause::Boot);
+        let dags = [
+            CausalDag::build(&[record(0, 0, 0, boot, EventData::None)]),
+            CausalDag::build(&[record(1, 0, 0, boot, EventData::None)]),
+        ];
+        let dot = render(&dags);
+
+        assert!(dot.contains("subgraph cluster_boot0 {"));
+        assert!(dot.contains("subgraph cluster_boot1 {"));
+        assert!(dot.contains("\"0/0:0\" [label="));
+        assert!(dot.contains("\"1/0:0\" [label="));
+    }
+}
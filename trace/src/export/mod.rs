@@ -0,0 +1,46 @@
+//! Renderers for the causal DAG.
+
+pub mod chrome;
+pub mod dot;
+
+use causality_core::schema::{self, EVENT_DATA_LEN, FieldType};
+use causality_core::types::{Cause, Event, EventId};
+
+fn node_name(id: EventId) -> String {
+    format!("{}:{}", id.core(), id.sequence())
+}
+
+fn event_label(event: &Event) -> String {
+    schema::kind_descriptor(event.kind.code()).map_or_else(|| format!("{:?}", event.kind), |kind| kind.name.to_string())
+}
+
+/// Payload fields as (name, formatted value), laid out by the schema descriptor table.
+/// 64-bit fields are addresses or sequence numbers often enough to print them in hex.
+fn data_fields(event: &Event) -> Vec<(&'static str, String)> {
+    let Some(descriptor) = schema::data_descriptor(event.data.tag() as u8) else {
+        return Vec::new();
+    };
+
+    let mut data = [0u8; EVENT_DATA_LEN];
+    event.data.encode(&mut data);
+
+    descriptor
+        .fields
+        .iter()
+        .map(|field| {
+            let value = field.read(&data);
+            let formatted = match field.ty {
+                FieldType::U64 => format!("{value:#x}"),
+                FieldType::U8 | FieldType::U16 | FieldType::U32 => value.to_string(),
+            };
+            (field.name, formatted)
+        })
+        .collect()
+}
+
+fn cause_label(cause: Cause) -> String {
+    match cause {
+        Cause::Root(root) => format!("root {root:?}"),
+        Cause::CausedBy(parent) => format!("caused by {}", node_name(parent)),
+    }
+}
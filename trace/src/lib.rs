@@ -0,0 +1,8 @@
+//! Host-side tooling for kernel causality traces: decodes the serial event stream,
+//! rebuilds the causal DAG, checks it against the event semantics spec and exports it
+//! for visualization.
+
+pub mod dag;
+pub mod decode;
+pub mod export;
+pub mod validate;
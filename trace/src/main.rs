@@ -0,0 +1,156 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+
+use causal_trace::dag::CausalDag;
+use causal_trace::export::{chrome, dot};
+use causal_trace::{decode, validate};
+use causality_core::schema;
+use causality_core::types::{DATA_DESCRIPTORS, KIND_DESCRIPTORS};
+
+const USAGE: &str = "usage: causal-trace <capture> [--dot <file>] [--chrome <file>]\n       causal-trace --schema";
+
+#[derive(Default)]
+struct Options {
+    capture: Option<String>,
+    dot: Option<String>,
+    chrome: Option<String>,
+    schema: bool,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut options = Options::default();
+    let mut args = args.peekable();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dot" => options.dot = Some(args.next()?),
+            "--chrome" => options.chrome = Some(args.next()?),
+            "--schema" => options.schema = true,
+            _ if options.capture.is_none() && !arg.starts_with("--") => options.capture = Some(arg),
+            _ => return None,
+        }
+    }
+
+    Some(options)
+}
+
+fn export(path: &str, writer: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> bool {
+    let result = File::create(path).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        writer(&mut out)?;
+        out.flush()
+    });
+
+    match result {
+        Ok(()) => {
+            println!("wrote {path}");
+            true
+        }
+        Err(err) => {
+            eprintln!("failed to write {path}: {err}");
+            false
+        }
+    }
+}
+
+/// Print the event kinds and payload layouts this build decodes.
+fn print_schema() {
+    for kind in KIND_DESCRIPTORS {
+        let data = schema::data_descriptor(kind.data_tag).map_or("?", |data| data.name);
+        println!("kind {:>3} {:<12} data {data}", kind.code, kind.name);
+    }
+    for data in DATA_DESCRIPTORS {
+        println!("data {:>3} {:<12} {} byte(s)", data.tag, data.name, data.size);
+        for field in data.fields {
+            println!("    +{:<2} {:<16} {:?}", field.offset, field.name, field.ty);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let Some(options) = parse_args(env::args().skip(1)) else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    if options.schema {
+        print_schema();
+        return ExitCode::SUCCESS;
+    }
+    let Options { capture: Some(path), dot, chrome, .. } = options else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("failed to read {path}: {err}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let capture = decode::decode(&bytes);
+    let mut dags = Vec::new();
+    let mut violations = Vec::new();
+    for records in capture.boots() {
+        let dag = CausalDag::build(records);
+        violations.extend(validate::validate(records, &dag).into_iter().map(|violation| (dag.boot(), violation)));
+        dags.push(dag);
+    }
+
+    println!(
+        "{} stream header(s), {} boot(s), {} record(s), {} unique event(s), {} byte(s) of other output",
+        capture.headers.len(),
+        capture.boots,
+        capture.records.len(),
+        dags.iter().map(CausalDag::len).sum::<usize>(),
+        capture.skipped_bytes
+    );
+    for (offset, err) in &capture.rejected {
+        println!("rejected record at {offset:#x}: {err:?}");
+    }
+
+    for dag in &dags {
+        println!("boot {}:", dag.boot());
+        for core in dag.cores() {
+            let sequences: Vec<u64> = dag.core_events(core).map(|event| event.id.sequence()).collect();
+            if let (Some(first), Some(last)) = (sequences.first(), sequences.last()) {
+                println!("  core {core}: {} event(s), sequences {first}..={last}", sequences.len());
+            }
+        }
+
+        for loss in dag.losses() {
+            println!(
+                "  core {} lost sequences {}..={} (reported by {}:{})",
+                loss.core,
+                loss.first_sequence,
+                loss.last_sequence,
+                loss.reported_by.core(),
+                loss.reported_by.sequence()
+            );
+        }
+    }
+
+    if let Some(dot_path) = &dot
+        && !export(dot_path, |out| dot::write(&dags, out))
+    {
+        return ExitCode::from(2);
+    }
+    if let Some(chrome_path) = &chrome
+        && !export(chrome_path, |out| chrome::write(&dags, &capture.headers, out))
+    {
+        return ExitCode::from(2);
+    }
+
+    if violations.is_empty() {
+        println!("all invariants hold");
+        return ExitCode::SUCCESS;
+    }
+
+    for (boot, violation) in &violations {
+        println!("violation in boot {boot}: {violation}");
+    }
+    ExitCode::FAILURE
+}
@@ -0,0 +1,287 @@
+//! Checks a decoded trace against the invariants in section 11 of
+//! `kernel/docs/event-semantics.md`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use causality_core::types::{Cause, EventId};
+
+use crate::dag::CausalDag;
+use crate::decode::Record;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// Invariant 1: the same id was seen with different contents.
+    DuplicateId { id: EventId },
+    /// Invariant 2: a core's streamed sequence did not increase within one stream segment.
+    NonMonotonicSequence {
+        core: u16,
+        previous: u64,
+        sequence: u64,
+        offset: usize,
+    },
+    /// Invariant 3: the parent is neither retained nor inside a declared dropped range.
+    DanglingParent { event: EventId, parent: EventId },
+    /// Invariant 4: sequences are missing without a loss event covering them.
+    UnreportedGap { core: u16, first: u64, last: u64 },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::DuplicateId { id } => {
+                write!(f, "duplicate id {}:{} with different contents", id.core(), id.sequence())
+            }
+            Violation::NonMonotonicSequence { core, previous, sequence, offset } => write!(
+                f,
+                "core {core} sequence {sequence} follows {previous} (offset {offset:#x})"
+            ),
+            Violation::DanglingParent { event, parent } => write!(
+                f,
+                "{}:{} is caused by {}:{} which is neither retained nor declared lost",
+                event.core(),
+                event.sequence(),
+                parent.core(),
+                parent.sequence()
+            ),
+            Violation::UnreportedGap { core, first, last } => {
+                write!(f, "core {core} sequences {first}..={last} missing without a loss event")
+            }
+        }
+    }
+}
+
+pub fn validate(records: &[Record], dag: &CausalDag) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    for id in dag.conflicts() {
+        violations.push(Violation::DuplicateId { id: *id });
+    }
+
+    check_monotonic(records, &mut violations);
+    check_parents(dag, &mut violations);
+    check_gaps(dag, &mut violations);
+
+    violations
+}
+
+fn check_monotonic(records: &[Record], violations: &mut Vec<Violation>) {
+    let mut last_seen: BTreeMap<(usize, u16), u64> = BTreeMap::new();
+
+    // Panic dumps are newest first by design.
+    for record in records.iter().filter(|record| !record.dumped) {
+        let id = record.event.id;
+        let key = (record.segment, id.core());
+        if let Some(&previous) = last_seen.get(&key)
+            && id.sequence() <= previous
+        {
+            violations.push(Violation::NonMonotonicSequence {
+                core: id.core(),
+                previous,
+                sequence: id.sequence(),
+                offset: record.offset,
+            });
+        }
+        last_seen.insert(key, id.sequence());
+    }
+}
+
+fn check_parents(dag: &CausalDag, violations: &mut Vec<Violation>) {
+    for event in dag.events() {
+        if let Cause::CausedBy(parent) = event.cause
+            && dag.get(parent).is_none()
+            && !dag.is_dropped(parent)
+        {
+            violations.push(Violation::DanglingParent { event: event.id, parent });
+        }
+    }
+}
+
+fn check_gaps(dag: &CausalDag, violations: &mut Vec<Violation>) {
+    for core in dag.cores() {
+        let losses = merged_losses(dag, core);
+        let mut expected = 0;
+        for event in dag.core_events(core) {
+            let sequence = event.id.sequence();
+            if sequence > expected {
+                report_gap(&losses, core, expected, sequence - 1, violations);
+            }
+            expected = sequence + 1;
+        }
+    }
+}
+
+/// Loss ranges declared for `core` as sorted, disjoint, non-adjacent inclusive ranges.
+fn merged_losses(dag: &CausalDag, core: u16) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = dag
+        .losses()
+        .iter()
+        .filter(|loss| loss.core == core)
+        .map(|loss| (loss.first_sequence, loss.last_sequence))
+        .collect();
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some((_, merged_last)) if first <= merged_last.saturating_add(1) => {
+                *merged_last = (*merged_last).max(last);
+            }
+            _ => merged.push((first, last)),
+        }
+    }
+    merged
+}
+
+/// Report the parts of `first..=last` on `core` not covered by `losses`, as returned by
+/// `merged_losses`.
+fn report_gap(losses: &[(u64, u64)], core: u16, first: u64, last: u64, violations: &mut Vec<Violation>) {
+    let mut next = first;
+    let overlapping = &losses[losses.partition_point(|&(_, loss_last)| loss_last < first)..];
+
+    for &(loss_first, loss_last) in overlapping {
+        if loss_first > last {
+            break;
+        }
+        if loss_first > next {
+            violations.push(Violation::UnreportedGap { core, first: next, last: loss_first - 1 });
+        }
+        if loss_last >= last {
+            return;
+        }
+        next = loss_last + 1;
+    }
+
+    violations.push(Violation::UnreportedGap { core, first: next, last });
+}
+
+#[cfg(test)]
+mod tests {
+    use causality_core::types::{Event, EventData, EventKind, RootCause};
+
+    use super::*;
+
+    fn record(segment: usize, dumped: bool, event: Event) -> Record {
+        Record { offset: segment * 0x100, segment, boot: 0, dumped, event }
+    }
+
+    fn event(core: u16, sequence: u64) -> Event {
+        let cause = match sequence {
+            0 => Cause::Root(RootCause::Boot),
+            _ => Cause::CausedBy(EventId::new(core, sequence - 1)),
+        };
+        Event {
+            id: EventId::new(core, sequence),
+            timestamp: sequence,
+            kind: EventKind::Boot,
+            cause,
+            data: EventData::None,
+        }
+    }
+
+    fn loss(core: u16, sequence: u64, root: RootCause, first_sequence: u64, last_sequence: u64) -> Event {
+        Event {
+            id: EventId::new(core, sequence),
+            timestamp: sequence,
+            kind: EventKind::Loss,
+            cause: Cause::Root(root),
+            data: EventData::Loss { core, first_sequence, last_sequence },
+        }
+    }
+
+    fn streamed(events: impl IntoIterator<Item = Event>) -> Vec<Record> {
+        events.into_iter().map(|event| record(0, false, event)).collect()
+    }
+
+    fn check(records: &[Record]) -> Vec<Violation> {
+        validate(records, &CausalDag::build(records))
+    }
+
+    #[test]
+    fn complete_trace_has_no_violations() {
+        let records = streamed((0..4).map(|sequence| event(0, sequence)).chain([event(1, 0)]));
+        assert_eq!(check(&records), []);
+    }
+
+    #[test]
+    fn duplicate_id_with_different_contents() {
+        let mut records = streamed([event(0, 0), event(0, 1)]);
+        records.push(record(1, true, Event { timestamp: 7, ..event(0, 1) }));
+        assert_eq!(check(&records), [Violation::DuplicateId { id: EventId::new(0, 1) }]);
+    }
+
+    #[test]
+    fn streamed_sequence_must_increase_but_dumps_may_run_backwards() {
+        let records = streamed([event(0, 0), event(0, 2), event(0, 1)]);
+        assert_eq!(
+            check(&records),
+            [Violation::NonMonotonicSequence { core: 0, previous: 2, sequence: 1, offset: 0 }]
+        );
+
+        let mut records = streamed([event(0, 0), event(0, 1)]);
+        records.extend([event(0, 2), event(0, 1), event(0, 0)].map(|event| record(1, true, event)));
+        assert_eq!(check(&records), []);
+    }
+
+    #[test]
+    fn parent_must_be_retained_or_declared_lost() {
+        let records = streamed([event(0, 0), event(0, 2)]);
+        assert_eq!(
+            check(&records),
+            [
+                Violation::DanglingParent { event: EventId::new(0, 2), parent: EventId::new(0, 1) },
+                Violation::UnreportedGap { core: 0, first: 1, last: 1 },
+            ]
+        );
+
+        let records = streamed([event(0, 0), event(0, 2), loss(0, 3, RootCause::Overflow, 1, 1)]);
+        assert_eq!(check(&records), []);
+    }
+
+    #[test]
+    fn gaps_are_reported_where_no_loss_range_covers_them() {
+        let records = streamed([
+            event(0, 0),
+            loss(0, 20, RootCause::Overflow, 3, 6),
+            loss(0, 21, RootCause::Overflow, 5, 8),
+            loss(0, 22, RootCause::Overflow, 12, 12),
+            loss(0, 23, RootCause::Overflow, 100, 200),
+        ]);
+        assert_eq!(
+            check(&records),
+            [
+                Violation::UnreportedGap { core: 0, first: 1, last: 2 },
+                Violation::UnreportedGap { core: 0, first: 9, last: 11 },
+                Violation::UnreportedGap { core: 0, first: 13, last: 19 },
+            ]
+        );
+    }
+
+    #[test]
+    fn panic_dump_window_with_declared_truncation_is_complete() {
+        // Streamed 0..=2; the dump declares 3..=5 as truncated and re-sends 6..=8.
+        let mut records = streamed((0..3).map(|sequence| event(0, sequence)));
+        let dumped = [event(0, 8), loss(0, 7, RootCause::Panic, 3, 5), event(0, 6)];
+        records.extend(dumped.map(|event| record(1, true, event)));
+        assert_eq!(check(&records), []);
+
+        // Without the loss event the truncation would be an unreported gap.
+        records.retain(|record| record.event.kind != EventKind::Loss);
+        assert!(check(&records).contains(&Violation::UnreportedGap { core: 0, first: 3, last: 5 }));
+    }
+
+    #[test]
+    fn reboot_restarts_sequences_without_duplicates() {
+        let mut records = streamed([event(0, 0), event(0, 1)]);
+        records.extend([event(0, 0), event(0, 1)].map(|event| Record {
+            boot: 1,
+            ..record(1, false, Event { timestamp: 1000 + event.timestamp, ..event })
+        }));
+
+        let capture = crate::decode::Capture { records, ..Default::default() };
+        for records in capture.boots() {
+            assert_eq!(check(records), []);
+        }
+        assert_eq!(capture.boots().count(), 2);
+    }
+}